@@ -0,0 +1,284 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use rocket::serde::json::serde_json;
+use serde::{Deserialize, Serialize};
+
+use super::{git, Actor, Manifest};
+
+/// The audit log is kept next to the manifest, one JSON entry per line.
+pub const AUDIT_FILE: &str = "audit.jsonl";
+
+/// A saved change to the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    /// The Slack user ID of who made the change, empty for ctrl itself.
+    pub slack_id: String,
+    pub name: String,
+    /// The command that made the change, e.g. `/ctrl github owner/repo`.
+    pub command: Option<String>,
+    pub changes: Vec<Diff>,
+}
+
+/// What part of the manifest a change affected.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", content = "name", rename_all = "snake_case")]
+pub enum Target {
+    Project(String),
+    /// A profile, by Slack user ID.
+    Profile(String),
+    /// The top-level settings, such as the managers.
+    Settings,
+}
+
+/// A target before and after a change as TOML, `None` where it didn't exist.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Diff {
+    pub target: Target,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// The top-level settings of the manifest, compared and restored together.
+#[derive(Serialize, Deserialize)]
+struct Settings {
+    configured_project: String,
+    managers: Vec<String>,
+}
+
+impl Settings {
+    fn of(manifest: &Manifest) -> Settings {
+        Settings {
+            configured_project: manifest.configured_project.clone(),
+            managers: manifest.managers.clone(),
+        }
+    }
+}
+
+fn path() -> PathBuf {
+    git::settings().manifest_path().with_file_name(AUDIT_FILE)
+}
+
+/// Reads the audit log, oldest entry first. Lines that can't be read are skipped.
+pub fn read_entries() -> Vec<Entry> {
+    let contents = match fs::read_to_string(path()) {
+        Ok(contents) => contents,
+        Err(_) => return vec![],
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                println!("Skipping unreadable audit log entry: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Appends what changed between `before` and `after` to the audit log. Called with
+/// the manifest locked, so entry IDs are handed out in order.
+pub fn record(before: &Manifest, after: &Manifest, actor: &Actor) {
+    let changes = diff(before, after);
+    if changes.is_empty() {
+        return;
+    }
+
+    let entry = Entry {
+        id: read_entries().last().map(|entry| entry.id + 1).unwrap_or(1),
+        time: Utc::now(),
+        slack_id: actor.slack_id.clone(),
+        name: actor.name.clone(),
+        command: actor.command.clone(),
+        changes,
+    };
+
+    let appended = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path())
+        .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(&entry).unwrap()));
+
+    match appended {
+        Ok(()) => println!("Recorded config change #{} by {}", entry.id, entry.name),
+        Err(e) => println!("Failed to write the audit log: {}", e),
+    }
+}
+
+/// The projects, profiles and settings that differ between two manifests.
+pub fn diff(before: &Manifest, after: &Manifest) -> Vec<Diff> {
+    let mut changes = vec![];
+
+    let mut projects = before
+        .projects
+        .keys()
+        .chain(after.projects.keys())
+        .collect::<Vec<_>>();
+    projects.sort();
+    projects.dedup();
+
+    for name in projects {
+        let (old, new) = (before.projects.get(name), after.projects.get(name));
+        if old != new {
+            changes.push(Diff {
+                target: Target::Project(name.clone()),
+                before: old.map(to_toml),
+                after: new.map(to_toml),
+            });
+        }
+    }
+
+    let mut profiles = before
+        .profiles
+        .keys()
+        .chain(after.profiles.keys())
+        .collect::<Vec<_>>();
+    profiles.sort();
+    profiles.dedup();
+
+    for slack_id in profiles {
+        let (old, new) = (before.profiles.get(slack_id), after.profiles.get(slack_id));
+        if old != new {
+            changes.push(Diff {
+                target: Target::Profile(slack_id.clone()),
+                before: old.map(to_toml),
+                after: new.map(to_toml),
+            });
+        }
+    }
+
+    let (old, new) = (
+        to_toml(&Settings::of(before)),
+        to_toml(&Settings::of(after)),
+    );
+    if old != new {
+        changes.push(Diff {
+            target: Target::Settings,
+            before: Some(old),
+            after: Some(new),
+        });
+    }
+
+    changes
+}
+
+fn to_toml<T: Serialize>(value: &T) -> String {
+    toml::to_string(value).unwrap_or_default()
+}
+
+/// The current TOML of a change's target, to compare with what the change left.
+fn current(manifest: &Manifest, target: &Target) -> Option<String> {
+    match target {
+        Target::Project(name) => manifest.projects.get(name).map(to_toml),
+        Target::Profile(slack_id) => manifest.profiles.get(slack_id).map(to_toml),
+        Target::Settings => Some(to_toml(&Settings::of(manifest))),
+    }
+}
+
+/// Puts the targets of `entry` back the way they were before it. Fails without
+/// changing anything if a target has changed again since, as reverting would
+/// also undo the later change.
+pub fn revert(manifest: &mut Manifest, entry: &Entry) -> Result<(), String> {
+    for change in &entry.changes {
+        if current(manifest, &change.target) != change.after {
+            return Err(format!(
+                "{} changed again after #{}, roll back the later changes first.",
+                describe(&change.target),
+                entry.id
+            ));
+        }
+    }
+
+    for change in &entry.changes {
+        let parse_error =
+            |e: toml::de::Error| format!("Can't restore {}: {}", describe(&change.target), e);
+
+        match (&change.target, &change.before) {
+            (Target::Project(name), Some(before)) => {
+                let project = toml::from_str(before).map_err(parse_error)?;
+                manifest.projects.insert(name.clone(), project);
+            }
+            (Target::Project(name), None) => {
+                manifest.projects.remove(name);
+            }
+            (Target::Profile(slack_id), Some(before)) => {
+                let profile = toml::from_str(before).map_err(parse_error)?;
+                manifest.profiles.insert(slack_id.clone(), profile);
+            }
+            (Target::Profile(slack_id), None) => {
+                manifest.profiles.remove(slack_id);
+            }
+            (Target::Settings, Some(before)) => {
+                let settings: Settings = toml::from_str(before).map_err(parse_error)?;
+                manifest.configured_project = settings.configured_project;
+                manifest.managers = settings.managers;
+            }
+            (Target::Settings, None) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Names a target for Slack, e.g. Project `web`.
+pub fn describe(target: &Target) -> String {
+    match target {
+        Target::Project(name) => format!("Project `{}`", name),
+        Target::Profile(slack_id) => format!("The profile of <@{}>", slack_id),
+        Target::Settings => "The global settings".to_string(),
+    }
+}
+
+/// The lines removed and added by a change, prefixed with `-` and `+`. Lines in a
+/// table are qualified with its name, e.g. `+ reminders.stale_after_hours = 48`.
+pub fn changed_lines(change: &Diff) -> Vec<String> {
+    let before = qualified_lines(change.before.as_deref().unwrap_or(""));
+    let mut after = qualified_lines(change.after.as_deref().unwrap_or(""));
+
+    let mut removed = vec![];
+    for line in before {
+        match after.iter().position(|other| *other == line) {
+            Some(position) => {
+                after.remove(position);
+            }
+            None => removed.push(format!("- {}", line)),
+        }
+    }
+
+    removed
+        .into_iter()
+        .chain(after.into_iter().map(|line| format!("+ {}", line)))
+        .collect()
+}
+
+fn qualified_lines(source: &str) -> Vec<String> {
+    let mut table = String::new();
+    let mut lines = vec![];
+
+    for line in source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if line.starts_with('[') {
+            table = line.trim_matches(|c| c == '[' || c == ']').to_string();
+            continue;
+        }
+
+        match table.is_empty() {
+            true => lines.push(line.to_string()),
+            false => lines.push(format!("{}.{}", table, line)),
+        }
+    }
+
+    lines
+}
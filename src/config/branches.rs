@@ -0,0 +1,131 @@
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use super::Project;
+
+/// What ctrl does with pull requests into base branches matching `pattern`.
+/// With neither reviewers nor auto-merge, ctrl only notifies the project channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BranchRule {
+    /// A glob such as `main` or `release/*`.
+    pub pattern: String,
+    pub assign_reviewers: bool,
+    pub auto_merge: bool,
+}
+
+impl BranchRule {
+    pub fn new(pattern: &str, assign_reviewers: bool, auto_merge: bool) -> BranchRule {
+        BranchRule {
+            pattern: pattern.to_string(),
+            assign_reviewers,
+            auto_merge,
+        }
+    }
+
+    /// A rule from a mode name as used in `/ctrl branches set`, e.g. `review-merge`.
+    pub fn from_mode(pattern: &str, mode: &str) -> Option<BranchRule> {
+        match mode {
+            "review-merge" => Some(BranchRule::new(pattern, true, true)),
+            "review" => Some(BranchRule::new(pattern, true, false)),
+            "merge" => Some(BranchRule::new(pattern, false, true)),
+            "notify" => Some(BranchRule::new(pattern, false, false)),
+            _ => None,
+        }
+    }
+
+    /// The mode name of this rule, the reverse of `from_mode`.
+    pub fn mode(&self) -> &'static str {
+        match (self.assign_reviewers, self.auto_merge) {
+            (true, true) => "review-merge",
+            (true, false) => "review",
+            (false, true) => "merge",
+            (false, false) => "notify",
+        }
+    }
+
+    pub fn matches(&self, branch: &str) -> bool {
+        Pattern::new(&self.pattern)
+            .map(|pattern| pattern.matches(branch))
+            .unwrap_or(false)
+    }
+
+    /// A short description for Slack, e.g. `reviewers + auto-merge`.
+    pub fn describe(&self) -> &'static str {
+        match (self.assign_reviewers, self.auto_merge) {
+            (true, true) => "reviewers + auto-merge",
+            (true, false) => "reviewers",
+            (false, true) => "auto-merge",
+            (false, false) => "notify only",
+        }
+    }
+}
+
+/// The policy for projects that have not set their own: reviews are requested and
+/// approved PRs are merged on every branch. Projects that protect some branches add
+/// rules for them with `/ctrl branches set`.
+pub fn default_branch_policy() -> Vec<BranchRule> {
+    vec![BranchRule::new("*", true, true)]
+}
+
+impl Project {
+    /// The rules checked in order, falling back to `default_branch_policy`.
+    pub fn branch_policy(&self) -> Vec<BranchRule> {
+        match self.branch_policy.is_empty() {
+            true => default_branch_policy(),
+            false => self.branch_policy.clone(),
+        }
+    }
+
+    /// The first rule matching `branch`. Branches that match no rule are only notified.
+    pub fn branch_rule(&self, branch: &str) -> BranchRule {
+        self.branch_policy()
+            .into_iter()
+            .find(|rule| rule.matches(branch))
+            .unwrap_or_else(|| BranchRule::new(branch, false, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_round_trip() {
+        for mode in ["review-merge", "review", "merge", "notify"] {
+            assert_eq!(BranchRule::from_mode("main", mode).unwrap().mode(), mode);
+        }
+        assert_eq!(BranchRule::from_mode("main", "deploy"), None);
+    }
+
+    #[test]
+    fn default_policy_treats_every_branch_alike() {
+        let project = Project::default();
+
+        assert_eq!(project.branch_rule("main").mode(), "review-merge");
+        assert_eq!(project.branch_rule("feature/login").mode(), "review-merge");
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let project = Project {
+            branch_policy: vec![
+                BranchRule::new("release/*", true, false),
+                BranchRule::new("*", false, true),
+            ],
+            ..Project::default()
+        };
+
+        assert_eq!(project.branch_rule("release/1.0").mode(), "review");
+        assert_eq!(project.branch_rule("develop").mode(), "merge");
+    }
+
+    #[test]
+    fn unmatched_branches_are_only_notified() {
+        let project = Project {
+            branch_policy: vec![BranchRule::new("release/*", true, true)],
+            ..Project::default()
+        };
+
+        assert_eq!(project.branch_rule("main").mode(), "notify");
+    }
+}
@@ -0,0 +1,647 @@
+use std::{
+    cell::{Cell, RefCell},
+    env, fs,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Sender},
+        Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+    thread,
+};
+
+use git2::{
+    build::CheckoutBuilder, Commit, Cred, CredentialType, FetchOptions, Oid, PushOptions, Remote,
+    RemoteCallbacks, Repository, Signature,
+};
+
+use super::{get_user_by_slack_id, read_manifest, schema, schema::ManifestError, Actor};
+use crate::error;
+
+/// The manifest's path inside the config repository.
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+/// The last commit of the config branch the working copy was synced with, pulled or
+/// pushed. Edits to the manifest since then are the ones still waiting to be pushed.
+const SYNCED_REF: &str = "refs/ctrl/synced";
+
+/// Holds the commit being pushed, so the config branch only moves once the push
+/// went through.
+const PUSH_REF: &str = "refs/ctrl/push";
+
+/// How many times to fetch and push again when the push is rejected because the
+/// branch moved in the meantime.
+const PUSH_ATTEMPTS: usize = 3;
+
+/// Where and how the manifest is synced, read from the environment.
+#[derive(Clone)]
+pub struct GitSettings {
+    /// The working copy of the config repository, `CONFIG_REPO_PATH` (default `.`).
+    pub path: PathBuf,
+    /// The branch changes are pushed to, `CONFIG_REPO_BRANCH` (default `master`).
+    pub branch: String,
+    /// `CONFIG_REPO_REMOTE`. Defaults to the repository's `origin`, or else the
+    /// manifest's `configured_project` on GitHub.
+    pub remote: Option<String>,
+    /// A private key to authenticate with over SSH, `CONFIG_REPO_SSH_KEY`.
+    pub ssh_key: Option<PathBuf>,
+    pub ssh_passphrase: Option<String>,
+    /// The user and token to authenticate with over HTTPS, `GITHUB_USER` and `GITHUB_TOKEN`.
+    pub user: String,
+    pub token: Option<String>,
+    /// Whether changes from Slack are proposed as pull requests on the config
+    /// repository rather than pushed, `CONFIG_CHANGES=pull-request`.
+    pub pull_requests: bool,
+}
+
+static SETTINGS: OnceLock<GitSettings> = OnceLock::new();
+
+/// The settings read from the environment on first use.
+pub fn settings() -> &'static GitSettings {
+    SETTINGS.get_or_init(GitSettings::from_env)
+}
+
+impl GitSettings {
+    fn from_env() -> GitSettings {
+        dotenv::dotenv().ok();
+
+        GitSettings {
+            path: PathBuf::from(env::var("CONFIG_REPO_PATH").unwrap_or(".".to_string())),
+            branch: env::var("CONFIG_REPO_BRANCH").unwrap_or("master".to_string()),
+            remote: env::var("CONFIG_REPO_REMOTE").ok(),
+            ssh_key: env::var("CONFIG_REPO_SSH_KEY").ok().map(PathBuf::from),
+            ssh_passphrase: env::var("CONFIG_REPO_SSH_PASSPHRASE").ok(),
+            user: env::var("GITHUB_USER").unwrap_or("x-access-token".to_string()),
+            token: env::var("GITHUB_TOKEN").ok(),
+            pull_requests: env::var("CONFIG_CHANGES").as_deref() == Ok("pull-request"),
+        }
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.path.join(MANIFEST_FILE)
+    }
+
+    fn remote_url(&self, repo: &Repository, configured_project: &str) -> String {
+        if let Some(remote) = &self.remote {
+            return remote.clone();
+        }
+
+        if let Some(url) = repo
+            .find_remote("origin")
+            .ok()
+            .and_then(|origin| origin.url().map(String::from))
+        {
+            return url;
+        }
+
+        match self.ssh_key {
+            Some(_) => format!("git@github.com:{}.git", configured_project),
+            None => format!("https://github.com/{}", configured_project),
+        }
+    }
+
+    fn callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        let tried = Cell::new(false);
+
+        callbacks.credentials(move |_, username, allowed| {
+            // libgit2 asks again when credentials are refused, so only offer them once
+            if tried.replace(true) {
+                return Err(git2::Error::from_str(
+                    "The config repository refused the credentials",
+                ));
+            }
+
+            if allowed.contains(CredentialType::SSH_KEY) {
+                if let Some(ssh_key) = &self.ssh_key {
+                    return Cred::ssh_key(
+                        username.unwrap_or("git"),
+                        None,
+                        ssh_key,
+                        self.ssh_passphrase.as_deref(),
+                    );
+                }
+            }
+
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                if let Some(token) = &self.token {
+                    return Cred::userpass_plaintext(&self.user, token);
+                }
+            }
+
+            Err(git2::Error::from_str(
+                "No credentials configured for the config repository",
+            ))
+        });
+
+        callbacks
+    }
+}
+
+/// A change to commit: who made it and where to push it.
+struct PushRequest {
+    configured_project: String,
+    actor: Actor,
+}
+
+static WRITER: OnceLock<Sender<PushRequest>> = OnceLock::new();
+
+/// Held while using the config repository, so pushes, pulls and proposals don't
+/// move its branches and working copy from under each other.
+static GIT: Mutex<()> = Mutex::new(());
+
+fn lock() -> MutexGuard<'static, ()> {
+    // Nothing is behind the lock, so a panic while holding it leaves nothing broken
+    GIT.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Asks the background writer to commit and push the manifest to `configured_project`.
+/// Requests that pile up while a push is running are handled by a single commit.
+pub fn queue_push(configured_project: &str, actor: &Actor) {
+    let writer = WRITER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<PushRequest>();
+
+        thread::spawn(move || {
+            while let Ok(request) = receiver.recv() {
+                let mut configured_project = request.configured_project;
+                let mut actors = vec![request.actor];
+
+                while let Ok(next) = receiver.try_recv() {
+                    configured_project = next.configured_project;
+                    if !actors.contains(&next.actor) {
+                        actors.push(next.actor);
+                    }
+                }
+
+                if let Err(e) = push_changes(settings(), &configured_project, &actors) {
+                    println!("Failed to push config changes: {}", e);
+                }
+            }
+        });
+
+        sender
+    });
+
+    let _ = writer.send(PushRequest {
+        configured_project: configured_project.to_string(),
+        actor: actor.clone(),
+    });
+}
+
+fn open(settings: &GitSettings) -> Result<Repository, git2::Error> {
+    match Repository::open(&settings.path) {
+        Ok(repo) => Ok(repo),
+        Err(_) => Repository::init(&settings.path),
+    }
+}
+
+/// Fetches the config branch, returning its tip.
+fn fetch<'r>(
+    repo: &'r Repository,
+    remote: &mut Remote,
+    settings: &GitSettings,
+) -> Result<Commit<'r>, git2::Error> {
+    let upstream = format!("refs/remotes/ctrl/{}", settings.branch);
+
+    remote.fetch(
+        &[format!("+refs/heads/{}:{}", settings.branch, upstream)],
+        Some(FetchOptions::new().remote_callbacks(settings.callbacks())),
+        None,
+    )?;
+
+    repo.find_reference(&upstream)?.peel_to_commit()
+}
+
+/// Commits `blob` as the manifest on top of `parent`, or returns `None` when that
+/// changes nothing.
+fn commit_manifest(
+    repo: &Repository,
+    parent: &Commit,
+    blob: Oid,
+    actors: &[Actor],
+) -> Result<Option<Oid>, git2::Error> {
+    // Replace only the manifest in the parent's tree
+    let mut builder = repo.treebuilder(Some(&parent.tree()?))?;
+    builder.insert(MANIFEST_FILE, blob, 0o100644)?;
+    let tree = repo.find_tree(builder.write()?)?;
+
+    if tree.id() == parent.tree_id() {
+        return Ok(None);
+    }
+
+    let (author, committer) = signatures(repo, actors)?;
+    let commit = repo.commit(
+        None,
+        &author,
+        &committer,
+        &commit_message(actors),
+        &tree,
+        &[parent],
+    )?;
+
+    Ok(Some(commit))
+}
+
+/// Moves the config branch to `commit`, updating the working copy when the branch
+/// is checked out.
+fn set_branch(
+    repo: &Repository,
+    settings: &GitSettings,
+    commit: &Commit,
+) -> Result<(), git2::Error> {
+    let branch = format!("refs/heads/{}", settings.branch);
+
+    let checked_out = repo
+        .head()
+        .ok()
+        .and_then(|head| head.name().map(String::from))
+        == Some(branch.clone());
+    if checked_out {
+        repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+    }
+
+    repo.reference(&branch, commit.id(), true, "ctrl: update config")?;
+
+    Ok(())
+}
+
+/// The commit the working copy was last synced with. Before the first sync that is
+/// the config branch, which was checked out from the remote.
+fn synced<'r>(repo: &'r Repository, settings: &GitSettings) -> Option<Commit<'r>> {
+    repo.find_reference(SYNCED_REF)
+        .or_else(|_| repo.find_reference(&format!("refs/heads/{}", settings.branch)))
+        .and_then(|reference| reference.peel_to_commit())
+        .ok()
+}
+
+/// Records that the working copy is in sync with `commit` of the config branch.
+fn set_synced(repo: &Repository, commit: &Commit) -> Result<(), git2::Error> {
+    repo.reference(SYNCED_REF, commit.id(), true, "ctrl: synced config")?;
+    Ok(())
+}
+
+/// Pushes `refspec`, returning the reason when the remote rejects it.
+fn push(
+    remote: &mut Remote,
+    settings: &GitSettings,
+    refspec: &str,
+) -> Result<Option<String>, git2::Error> {
+    let rejection = RefCell::new(None);
+    let mut callbacks = settings.callbacks();
+    callbacks.push_update_reference(|_, status| {
+        *rejection.borrow_mut() = status.map(String::from);
+        Ok(())
+    });
+
+    let pushed = remote.push(
+        &[refspec],
+        Some(PushOptions::new().remote_callbacks(callbacks)),
+    );
+
+    match pushed {
+        Ok(()) => Ok(rejection.take()),
+        Err(e) if e.code() == git2::ErrorCode::NotFastForward => {
+            Ok(Some("non-fast-forward".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Commits the manifest on top of the config branch and pushes it. The branch is
+/// fetched first and the commit is rebuilt on the fetched tip, so upstream changes
+/// to other files are kept and upstream changes to the manifest are merged, and this
+/// is retried if the push is rejected because the branch moved again. The local
+/// branch only moves once the push went through, so a change that couldn't be pushed
+/// is still waiting the next time.
+pub fn push_changes(
+    settings: &GitSettings,
+    configured_project: &str,
+    actors: &[Actor],
+) -> Result<(), git2::Error> {
+    let _git = lock();
+
+    let repo = open(settings)?;
+    let url = settings.remote_url(&repo, configured_project);
+    let branch = format!("refs/heads/{}", settings.branch);
+
+    for attempt in 1..=PUSH_ATTEMPTS {
+        let mut remote = repo.remote_anonymous(&url)?;
+        let parent = fetch(&repo, &mut remote, settings)?;
+
+        let local = repo.blob_path(&settings.manifest_path())?;
+        let blob = match synced(&repo, settings) {
+            Some(base) if manifest_blob(&base) != manifest_blob(&parent) => {
+                match merge_upstream(&repo, settings, &base, &parent, local)? {
+                    Some(merged) => {
+                        // The working copy now has the upstream edits too
+                        set_synced(&repo, &parent)?;
+                        merged
+                    }
+                    // Saved again while merging, start over with the new save
+                    None => continue,
+                }
+            }
+            _ => local,
+        };
+
+        let commit = match commit_manifest(&repo, &parent, blob, actors)? {
+            Some(commit) => repo.find_commit(commit)?,
+            None => {
+                println!("Config is already up to date");
+                set_branch(&repo, settings, &parent)?;
+                set_synced(&repo, &parent)?;
+                return Ok(());
+            }
+        };
+
+        let mut pending = repo.reference(PUSH_REF, commit.id(), true, "ctrl: push config")?;
+        let rejection = push(&mut remote, settings, &format!("{}:{}", PUSH_REF, branch));
+        pending.delete()?;
+
+        match rejection? {
+            None => {
+                set_branch(&repo, settings, &commit)?;
+                set_synced(&repo, &commit)?;
+                println!("Pushed config changes to {}", settings.branch);
+                return Ok(());
+            }
+            Some(rejection) => println!(
+                "Config push rejected ({}), attempt {} of {}",
+                rejection, attempt, PUSH_ATTEMPTS
+            ),
+        }
+    }
+
+    Err(git2::Error::from_str(
+        "The config branch kept moving while pushing",
+    ))
+}
+
+/// The manifest's blob in `commit`, if it has one.
+fn manifest_blob(commit: &Commit) -> Option<Oid> {
+    commit
+        .tree()
+        .ok()?
+        .get_name(MANIFEST_FILE)
+        .map(|entry| entry.id())
+}
+
+/// Merges the manifest edited upstream since `base` with the `local` one, so pushing
+/// doesn't overwrite edits made by hand or in merged config pull requests. The merge
+/// is written to the working copy and swapped in, or `None` is returned when the
+/// manifest was saved again in the meantime. Fails when the edits conflict or the
+/// merged manifest is invalid, leaving both sides as they were.
+fn merge_upstream(
+    repo: &Repository,
+    settings: &GitSettings,
+    base: &Commit,
+    upstream: &Commit,
+    local: Oid,
+) -> Result<Option<Oid>, git2::Error> {
+    let mut builder = repo.treebuilder(Some(&base.tree()?))?;
+    builder.insert(MANIFEST_FILE, local, 0o100644)?;
+    let ours = repo.find_tree(builder.write()?)?;
+
+    let mut index = repo.merge_trees(&base.tree()?, &ours, &upstream.tree()?, None)?;
+    if index.has_conflicts() {
+        return Err(git2::Error::from_str(
+            "The manifest was changed on the config branch in a way that conflicts with changes from Slack, resolve it by hand",
+        ));
+    }
+
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    let merged = tree
+        .get_name(MANIFEST_FILE)
+        .ok_or(git2::Error::from_str("The merged config has no manifest"))?
+        .id();
+    let blob = repo.find_blob(merged)?;
+    let contents =
+        std::str::from_utf8(blob.content()).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+    let manifest = schema::parse(contents).map_err(|e| {
+        git2::Error::from_str(&format!(
+            "The manifest merged with the config branch is invalid: {}",
+            e
+        ))
+    })?;
+
+    // Run by the background writer, outside the async runtime
+    let _update = super::UPDATES.blocking_lock();
+    if repo.blob_path(&settings.manifest_path())? != local {
+        return Ok(None);
+    }
+    fs::write(settings.manifest_path(), contents)
+        .map_err(|e| git2::Error::from_str(&e.to_string()))?;
+    let actor = Actor {
+        name: "ctrl merging the config branch".to_string(),
+        ..Actor::ctrl()
+    };
+    super::swap_manifest(&read_manifest(), manifest, &actor);
+
+    println!("Merged manifest changes from the config branch");
+    Ok(Some(merged))
+}
+
+/// Brings the working copy up to date with the config branch. The fetched manifest
+/// is validated first and the working copy is left alone if it is invalid. When
+/// changes from Slack are still waiting to be pushed, the push merges them instead.
+pub fn pull(settings: &GitSettings, configured_project: &str) -> error::Result<()> {
+    let _git = lock();
+
+    let repo = open(settings)?;
+    let mut remote = repo.remote_anonymous(&settings.remote_url(&repo, configured_project))?;
+    let upstream = fetch(&repo, &mut remote, settings)?;
+
+    if let Some(blob) = manifest_blob(&upstream) {
+        let blob = repo.find_blob(blob)?;
+        let contents =
+            std::str::from_utf8(blob.content()).map_err(|e| ManifestError::Read(e.to_string()))?;
+        schema::parse(contents)?;
+    }
+
+    let base = synced(&repo, settings);
+    let local = repo.blob_path(&settings.manifest_path()).ok();
+    if base.is_some() && local != base.as_ref().and_then(manifest_blob) {
+        println!("Config changes are waiting to be pushed, merging upstream changes then");
+        return Ok(());
+    }
+
+    set_branch(&repo, settings, &upstream)?;
+    set_synced(&repo, &upstream)?;
+    Ok(())
+}
+
+/// Commits `contents` as the manifest on a new branch `name` off the config branch
+/// and pushes it, without touching the working copy.
+pub fn push_branch(
+    settings: &GitSettings,
+    configured_project: &str,
+    name: &str,
+    contents: &str,
+    actor: &Actor,
+) -> Result<(), git2::Error> {
+    let _git = lock();
+
+    let repo = open(settings)?;
+    let mut remote = repo.remote_anonymous(&settings.remote_url(&repo, configured_project))?;
+    let parent = fetch(&repo, &mut remote, settings)?;
+
+    let blob = repo.blob(contents.as_bytes())?;
+    let commit = match commit_manifest(&repo, &parent, blob, std::slice::from_ref(actor))? {
+        Some(commit) => commit,
+        None => {
+            return Err(git2::Error::from_str(
+                "The change is already on the config branch",
+            ))
+        }
+    };
+
+    let branch = format!("refs/heads/{}", name);
+    let mut reference = repo.reference(&branch, commit, true, "ctrl: propose config change")?;
+    let rejection = push(&mut remote, settings, &format!("{}:{}", branch, branch));
+    reference.delete()?;
+
+    match rejection? {
+        None => Ok(()),
+        Some(rejection) => Err(git2::Error::from_str(&format!(
+            "The config repository rejected {}: {}",
+            name, rejection
+        ))),
+    }
+}
+
+/// The author is the Slack user who made the change, using their GitHub noreply
+/// address when they have linked a GitHub account. When several people's changes
+/// are pushed together the bot is the author and the message lists them.
+fn signatures(
+    repo: &Repository,
+    actors: &[Actor],
+) -> Result<(Signature<'static>, Signature<'static>), git2::Error> {
+    let committer = match repo.signature() {
+        Ok(signature) => signature.to_owned(),
+        Err(_) => Signature::now("ctrl", "ctrl@users.noreply.github.com")?,
+    };
+
+    let author = match actors {
+        [actor] => {
+            let manifest = read_manifest();
+            match get_user_by_slack_id(&manifest, &actor.slack_id) {
+                Some(profile) if !profile.github_username.is_empty() => Signature::now(
+                    &actor.name,
+                    &format!("{}@users.noreply.github.com", profile.github_username),
+                )?,
+                _ => Signature::now(&actor.name, committer.email().unwrap_or(""))?,
+            }
+        }
+        _ => committer.clone(),
+    };
+
+    Ok((author, committer))
+}
+
+fn commit_message(actors: &[Actor]) -> String {
+    let names = actors
+        .iter()
+        .map(|actor| match actor.slack_id.is_empty() {
+            true => actor.name.clone(),
+            false => format!("{} ({})", actor.name, actor.slack_id),
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!("Updated config\n\nChanged by {}.", names)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::build::RepoBuilder;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const MANIFEST: &str =
+        "version = 1\nmanagers = []\nconfigured_project = \"ctrl\"\n\n[projects]\n\n[profiles]\n";
+
+    /// A bare remote with the manifest on `master`, and a working copy cloned from it.
+    fn repositories() -> (TempDir, GitSettings) {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = Repository::init_bare(dir.path().join("remote")).unwrap();
+
+        let blob = remote.blob(MANIFEST.as_bytes()).unwrap();
+        let mut builder = remote.treebuilder(None).unwrap();
+        builder.insert(MANIFEST_FILE, blob, 0o100644).unwrap();
+        let tree = remote.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::now("ctrl", "ctrl@users.noreply.github.com").unwrap();
+        remote
+            .commit(
+                Some("refs/heads/master"),
+                &signature,
+                &signature,
+                "Add manifest",
+                &tree,
+                &[],
+            )
+            .unwrap();
+
+        let url = dir.path().join("remote").to_string_lossy().to_string();
+        RepoBuilder::new()
+            .branch("master")
+            .clone(&url, &dir.path().join("config"))
+            .unwrap();
+
+        let settings = GitSettings {
+            path: dir.path().join("config"),
+            branch: "master".to_string(),
+            remote: Some(url),
+            ssh_key: None,
+            ssh_passphrase: None,
+            user: "x-access-token".to_string(),
+            token: None,
+            pull_requests: false,
+        };
+
+        (dir, settings)
+    }
+
+    fn remote_manifest(dir: &TempDir) -> String {
+        let remote = Repository::open_bare(dir.path().join("remote")).unwrap();
+        let commit = remote
+            .find_reference("refs/heads/master")
+            .and_then(|reference| reference.peel_to_commit())
+            .unwrap();
+        let blob = remote.find_blob(manifest_blob(&commit).unwrap()).unwrap();
+
+        String::from_utf8(blob.content().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn changes_survive_a_failed_push() {
+        let (dir, settings) = repositories();
+        // Changes from more than one person are committed as ctrl
+        let actors = [Actor::new("U1", "one"), Actor::new("U2", "two")];
+
+        let edited = MANIFEST.replace("managers = []", "managers = [\"octocat\"]");
+        fs::write(settings.manifest_path(), &edited).unwrap();
+
+        // A lock on the remote branch makes the push fail
+        let lock = dir.path().join("remote/refs/heads/master.lock");
+        fs::write(&lock, "").unwrap();
+        assert!(push_changes(&settings, "ctrl", &actors).is_err());
+        fs::remove_file(&lock).unwrap();
+        assert_eq!(remote_manifest(&dir), MANIFEST);
+
+        // Pulling in the meantime leaves the change waiting to be pushed
+        pull(&settings, "ctrl").unwrap();
+        assert_eq!(
+            fs::read_to_string(settings.manifest_path()).unwrap(),
+            edited
+        );
+
+        push_changes(&settings, "ctrl", &actors).unwrap();
+        assert_eq!(remote_manifest(&dir), edited);
+
+        pull(&settings, "ctrl").unwrap();
+        assert_eq!(
+            fs::read_to_string(settings.manifest_path()).unwrap(),
+            edited
+        );
+    }
+}
@@ -1,38 +1,202 @@
 use std::{
-    collections::HashMap,
-    env,
-    fs::File,
-    io::{Read, Write},
-    path::Path,
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    sync::{OnceLock, RwLock},
 };
 
-use git2::{Cred, PushOptions, RemoteCallbacks, Repository, CredentialType};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use self::{branches::BranchRule, schema::ManifestError};
+use crate::error::{self, Error};
+
+pub mod audit;
+pub mod branches;
+pub mod git;
+pub mod proposals;
+pub mod schema;
+pub mod state;
+pub mod store;
+pub mod validate;
+pub mod watch;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct Project {
     pub slack_channel: String,
     pub github_repo: Option<String>,
     pub project_owners: Vec<String>,
     pub jira_project: Option<String>,
+    #[serde(default, skip_serializing_if = "JiraStatuses::is_empty")]
+    pub jira_statuses: JiraStatuses,
+    pub reminders: Option<Reminders>,
+    pub digest: Option<Digest>,
+    /// Rules by base branch, see `branches::default_branch_policy` for when this is empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branch_policy: Vec<BranchRule>,
+    #[serde(default, skip_serializing_if = "MergeSettings::is_default")]
+    pub merge: MergeSettings,
+    #[serde(default, skip_serializing_if = "ReviewerStrategy::is_all")]
+    pub reviewers: ReviewerStrategy,
+}
+
+/// The Jira statuses that linked issues are moved to as their pull requests progress.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct JiraStatuses {
+    pub opened: Option<String>,
+    pub approved: Option<String>,
+    pub merged: Option<String>,
+}
+
+impl JiraStatuses {
+    pub fn is_empty(&self) -> bool {
+        self.opened.is_none() && self.approved.is_none() && self.merged.is_none()
+    }
+}
+
+/// How reviewers are picked from the project owners and managers when a pull
+/// request is opened.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "strategy", rename_all = "kebab-case")]
+pub enum ReviewerStrategy {
+    /// Everyone.
+    #[default]
+    All,
+    /// `count` reviewers picked at random.
+    Random { count: usize },
+    /// The next `count` reviewers in turn, remembering where the last pull request left off.
+    RoundRobin { count: usize },
+    /// The `count` reviewers with the fewest review requests on open pull requests.
+    LeastLoaded { count: usize },
+}
+
+impl ReviewerStrategy {
+    pub fn is_all(&self) -> bool {
+        *self == ReviewerStrategy::All
+    }
+}
+
+/// How and when approved pull requests are merged automatically.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MergeSettings {
+    #[serde(default)]
+    pub method: MergeMethod,
+    /// How many distinct reviewers must have approved, counting their latest review.
+    #[serde(default = "default_required_approvals")]
+    pub required_approvals: usize,
+    /// Whether one of the approvals must come from the project's owners, rather
+    /// than only from global managers.
+    #[serde(default)]
+    pub require_owner_approval: bool,
+    /// Names of the check runs and commit statuses that must pass before merging.
+    /// When empty, every check reported on the pull request must pass.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_checks: Vec<String>,
+}
+
+impl Default for MergeSettings {
+    fn default() -> Self {
+        MergeSettings {
+            method: MergeMethod::default(),
+            required_approvals: default_required_approvals(),
+            require_owner_approval: false,
+            required_checks: vec![],
+        }
+    }
+}
+
+impl MergeSettings {
+    pub fn is_default(&self) -> bool {
+        *self == MergeSettings::default()
+    }
+}
+
+fn default_required_approvals() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMethod {
+    #[default]
+    Merge,
+    Squash,
+    Rebase,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl MergeMethod {
+    /// A method by its name in the manifest, e.g. `squash`.
+    pub fn parse(name: &str) -> Option<MergeMethod> {
+        match name {
+            "merge" => Some(MergeMethod::Merge),
+            "squash" => Some(MergeMethod::Squash),
+            "rebase" => Some(MergeMethod::Rebase),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MergeMethod::Merge => "merge",
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+        }
+    }
+}
+
+/// When to remind reviewers about pull requests that are waiting on them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reminders {
+    /// A cron expression with seconds, e.g. `0 0 9 * * Mon-Fri`, evaluated in UTC.
+    pub schedule: String,
+    /// How long a pull request can wait on review before reviewers are reminded.
+    #[serde(default = "default_stale_after_hours")]
+    pub stale_after_hours: i64,
+}
+
+fn default_stale_after_hours() -> i64 {
+    24
+}
+
+/// When to post a digest of the project's activity.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Digest {
+    /// A cron expression with seconds, e.g. `0 0 9 * * Mon` for Monday mornings.
+    pub schedule: String,
+    /// The IANA timezone the schedule is evaluated in, e.g. `Europe/London`. Defaults to UTC.
+    pub timezone: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct Profile {
     pub github_username: String,
+    pub jira_account_id: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Manifest {
+    /// The schema version, see `schema::CURRENT_VERSION`.
+    #[serde(default)]
+    pub version: u32,
+    #[serde(serialize_with = "ordered")]
     pub projects: HashMap<String, Project>,
     pub managers: Vec<String>,
     pub configured_project: String,
+    #[serde(serialize_with = "ordered")]
     pub profiles: HashMap<String, Profile>,
 }
 
+/// Writes a map in key order, so saving the manifest only changes the lines that
+/// actually changed.
+fn ordered<S: Serializer, V: Serialize>(
+    map: &HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
 impl Default for Manifest {
     fn default() -> Self {
         Manifest {
+            version: schema::CURRENT_VERSION,
             projects: HashMap::new(),
             managers: Vec::new(),
             configured_project: "amcwb/ctrl".to_string(),
@@ -67,13 +231,31 @@ pub fn get_slack_by_github_username<'a>(
         .map(|(slack_id, _)| slack_id.as_str())
 }
 
+pub fn get_slack_by_jira_account_id<'a>(
+    manifest: &'a Manifest,
+    jira_account_id: &str,
+) -> Option<&'a str> {
+    manifest
+        .profiles
+        .iter()
+        .find(|(_, profile)| profile.jira_account_id.as_deref() == Some(jira_account_id))
+        .map(|(slack_id, _)| slack_id.as_str())
+}
+
 pub fn set_user_github_username(manifest: &mut Manifest, slack_id: &str, github_username: &str) {
-    manifest.profiles.insert(
-        slack_id.to_string(),
-        Profile {
-            github_username: github_username.to_string(),
-        },
-    );
+    manifest
+        .profiles
+        .entry(slack_id.to_string())
+        .or_default()
+        .github_username = github_username.to_string();
+}
+
+pub fn set_user_jira_account_id(manifest: &mut Manifest, slack_id: &str, jira_account_id: &str) {
+    manifest
+        .profiles
+        .entry(slack_id.to_string())
+        .or_default()
+        .jira_account_id = Some(jira_account_id.to_string());
 }
 
 pub fn get_user_by_slack_mention<'a>(
@@ -83,7 +265,7 @@ pub fn get_user_by_slack_mention<'a>(
     manifest
         .profiles
         .iter()
-        .find(|(slack_id, profile)| {
+        .find(|(slack_id, _)| {
             slack_id.as_str()
                 == slack_mention
                     .trim_start_matches("<@")
@@ -105,24 +287,25 @@ pub fn get_project_name_by_slack_channel<'a>(
         .map(|(project_name, _)| project_name.as_str())
 }
 
-pub fn get_project_by_slack_channel<'a>(
+pub fn get_project_by_github_repo<'a>(
     manifest: &'a Manifest,
-    slack_channel: &str,
+    github_repo: &str,
 ) -> Option<&'a Project> {
     manifest
         .projects
         .values()
-        .find(|project| project.slack_channel == slack_channel)
+        .find(|project| project.github_repo.as_ref().unwrap_or(&"".to_string()) == github_repo)
 }
 
-pub fn get_project_by_github_repo<'a>(
+pub fn get_project_name_by_github_repo<'a>(
     manifest: &'a Manifest,
     github_repo: &str,
-) -> Option<&'a Project> {
+) -> Option<&'a str> {
     manifest
         .projects
-        .values()
-        .find(|project| project.github_repo.as_ref().unwrap_or(&"".to_string()) == github_repo)
+        .iter()
+        .find(|(_, project)| project.github_repo.as_deref() == Some(github_repo))
+        .map(|(project_name, _)| project_name.as_str())
 }
 
 pub fn get_project_by_jira_project<'a>(
@@ -139,96 +322,185 @@ pub fn get_project_by_name<'a>(manifest: &'a Manifest, project_name: &str) -> Op
     manifest.projects.get(project_name)
 }
 
-pub fn write_manifest(manifest: &Manifest) {
-    let mut file = File::create("manifest.toml").unwrap();
-
-    let manifest_json = toml::to_string_pretty(&manifest).unwrap();
-    file.write_all(manifest_json.as_bytes()).unwrap();
-    let _ = file.sync_all();
-    drop(file);
+/// Who changed the manifest, recorded with the change in the config repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor {
+    /// The Slack user ID, empty for changes ctrl makes by itself.
+    pub slack_id: String,
+    pub name: String,
+    /// The command that made the change, recorded in the audit log.
+    pub command: Option<String>,
+}
 
-    println!("Wrote manifest.json");
-    println!("{:?}", manifest);
+impl Actor {
+    pub fn new(slack_id: &str, name: &str) -> Actor {
+        Actor {
+            slack_id: slack_id.to_string(),
+            name: name.to_string(),
+            command: None,
+        }
+    }
 
-    let disabled = env::var("GITHUB_PUSH_DISABLE").unwrap_or("0".to_string());
-    if disabled != "1" {
-        push_changes(manifest);
+    /// ctrl itself, e.g. when it reloads the manifest after it was edited elsewhere.
+    pub fn ctrl() -> Actor {
+        Actor::new("", "ctrl")
     }
 }
 
+static MANIFEST: OnceLock<RwLock<Manifest>> = OnceLock::new();
+
+/// Held while the manifest is changed and saved, so changes, reloads and merges
+/// can't overwrite each other. Readers only wait on `MANIFEST` for the swap.
+static UPDATES: ::rocket::tokio::sync::Mutex<()> = ::rocket::tokio::sync::Mutex::const_new(());
+
+fn manifest() -> &'static RwLock<Manifest> {
+    MANIFEST.get_or_init(|| match store::store().load() {
+        Ok(manifest) => RwLock::new(manifest),
+        Err(e) => panic!("Failed to load the manifest:\n{}", e),
+    })
+}
+
+/// Loads the manifest from the store. Later reads and updates use the copy in memory.
+pub fn load_manifest() {
+    manifest();
+}
+
+/// A snapshot of the manifest. Changes to it are not saved, use `update_manifest` instead.
 pub fn read_manifest() -> Manifest {
-    if !Path::new("manifest.toml").exists() {
-        write_manifest(&Default::default());
+    manifest().read().unwrap().clone()
+}
+
+/// Reads the manifest from the store again and swaps it in, returning whether it
+/// changed. The manifest in memory is kept when the stored one can't be read or
+/// fails validation. The change is recorded in the audit log as made by `actor`.
+pub async fn reload_manifest(actor: &Actor) -> Result<bool, ManifestError> {
+    let _update = UPDATES.lock().await;
+    let loaded = store::store().load()?;
+
+    let current = read_manifest();
+    if current == loaded {
+        return Ok(false);
     }
 
-    let mut file = File::open("manifest.toml").unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
+    swap_manifest(&current, loaded, actor);
+    Ok(true)
+}
 
-    let manifest: Manifest = toml::from_str(&contents).unwrap_or(Default::default());
+/// Swaps in `updated`, recording the change from `current` as made by `actor`.
+/// Callers hold `UPDATES`, so `current` is still what is in memory.
+fn swap_manifest(current: &Manifest, updated: Manifest, actor: &Actor) {
+    audit::record(current, &updated, actor);
+    *manifest().write().unwrap() = updated;
+}
 
-    drop(file);
+/// The result of `update_manifest`.
+pub struct Change<T> {
+    /// What the update returned.
+    pub value: T,
+    pub status: ChangeStatus,
+}
 
-    println!("Read manifest.json");
-    println!("{:?}", manifest);
-    manifest
+pub enum ChangeStatus {
+    /// The change was saved, or there was nothing to change.
+    Saved,
+    /// The change was proposed in this pull request on the config repository and
+    /// takes effect once it is merged.
+    Proposed(String),
+    /// Opening the pull request for the change failed.
+    Failed(String),
+    /// The change couldn't be saved, and the manifest was left as it was.
+    Rejected(ManifestError),
 }
 
-pub fn push_changes(manifest: &Manifest) {
-    let repo = match Repository::init(".") {
-        Ok(repo) => repo,
-        Err(e) => panic!("failed to init: {}", e),
-    };
+/// Applies `update` to the manifest on behalf of `actor` and saves the result, one
+/// update at a time so concurrent updates can't overwrite each other.
+/// With `CONFIG_CHANGES=pull-request` the change is proposed instead.
+pub async fn update_manifest<T>(
+    actor: &Actor,
+    update: impl FnOnce(&mut Manifest) -> T,
+) -> Change<T> {
+    match try_update_manifest(actor, |manifest| Ok::<T, Infallible>(update(manifest))).await {
+        Ok(change) => change,
+        Err(never) => match never {},
+    }
+}
 
-    let mut index = repo.index().unwrap();
-    index
-        .add_all(&["."], git2::IndexAddOption::DEFAULT, None)
-        .unwrap();
-    index.write().unwrap();
-
-    let oid = index.write_tree().unwrap();
-    let signature = repo.signature().unwrap();
-    let head = repo.head().unwrap();
-    let parent_commit = head.peel_to_commit().unwrap();
-    let tree = repo.find_tree(oid).unwrap();
-    repo.commit(
-        Some("HEAD"),
-        &signature,
-        &signature,
-        "Updated config",
-        &tree,
-        &[&parent_commit],
-    )
-    .expect("Commit failed");
-
-    let mut cb = RemoteCallbacks::new();
-    cb.credentials(|_, _, _cred| {
-        println!("Cred callback {:?}", _cred);
-        if !_cred.contains(CredentialType::USER_PASS_PLAINTEXT) {
-            return Err(git2::Error::from_str("Only username/password supported"));
+/// Like `update_manifest`, but the changes are thrown away if `update` returns an error.
+/// Nothing is saved when the manifest is unchanged, and changes that leave it invalid
+/// are rejected with the problems found.
+pub async fn try_update_manifest<T, E>(
+    actor: &Actor,
+    update: impl FnOnce(&mut Manifest) -> Result<T, E>,
+) -> Result<Change<T>, E> {
+    if !git::settings().pull_requests {
+        let _update = UPDATES.lock().await;
+
+        let current = read_manifest();
+        let mut updated = current.clone();
+        let value = update(&mut updated)?;
+
+        if updated == current {
+            return Ok(Change {
+                value,
+                status: ChangeStatus::Saved,
+            });
         }
 
-        let creds = Cred::userpass_plaintext(
-            env::var("GITHUB_USER").expect("username not set").as_str(),
-            env::var("GITHUB_TOKEN").expect("token not set").as_str(),
-        );
-
-        creds
-    });
-
-    let mut remote = repo
-        .find_remote("origin")
-        .or_else(|_| {
-            repo.remote(
-                "origin",
-                format!("https://github.com/{}", manifest.configured_project).as_str(),
-            )
-        })
-        .expect("Remote failed");
-    remote
-        .push(
-            &["refs/heads/master:refs/heads/master"],
-            Some(&mut PushOptions::new().remote_callbacks(cb)),
-        )
-        .expect("Push failed");
+        let saved = match schema::ensure_valid(&updated) {
+            Ok(()) => {
+                let (manifest, saver) = (updated.clone(), actor.clone());
+                // Saving writes to disk or the database, away from the async workers
+                ::rocket::tokio::task::spawn_blocking(move || {
+                    store::store().save(&manifest, &saver)
+                })
+                .await
+                .unwrap_or_else(|e| Err(ManifestError::Write(e.to_string())))
+            }
+            Err(e) => Err(e),
+        };
+
+        let status = match saved {
+            // Only swap the change in once the store has it
+            Ok(()) => {
+                swap_manifest(&current, updated, actor);
+                ChangeStatus::Saved
+            }
+            // Invalid changes are the user's to fix, only failed saves are reported
+            Err(e @ ManifestError::Invalid(_)) => ChangeStatus::Rejected(e),
+            Err(e) => {
+                error::report(&Error::Config(e.clone()), "Saving the manifest", None).await;
+                ChangeStatus::Rejected(e)
+            }
+        };
+
+        return Ok(Change { value, status });
+    }
+
+    let current = read_manifest();
+    let mut updated = current.clone();
+    let value = update(&mut updated)?;
+
+    if updated == current {
+        return Ok(Change {
+            value,
+            status: ChangeStatus::Saved,
+        });
+    }
+
+    if let Err(e) = schema::ensure_valid(&updated) {
+        return Ok(Change {
+            value,
+            status: ChangeStatus::Rejected(e),
+        });
+    }
+
+    let status = match proposals::propose(&current, &updated, actor).await {
+        Ok(url) => ChangeStatus::Proposed(url),
+        Err(e) => {
+            println!("Failed to propose config change: {}", e);
+            ChangeStatus::Failed(e)
+        }
+    };
+
+    Ok(Change { value, status })
 }
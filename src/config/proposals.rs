@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+
+use chrono::Utc;
+
+use super::{git, Actor, Manifest};
+
+/// Proposes `updated` as a pull request on the config repository, returning its URL.
+/// The manifest in memory is left alone until the pull request is merged and the
+/// config branch is pulled.
+pub async fn propose(
+    current: &Manifest,
+    updated: &Manifest,
+    actor: &Actor,
+) -> Result<String, String> {
+    let settings = git::settings();
+    let contents = toml::to_string_pretty(updated).map_err(|e| e.to_string())?;
+    let branch = format!("ctrl/config-{}", Utc::now().format("%Y%m%d-%H%M%S%3f"));
+
+    let (owner, repo) = updated.configured_project.split_once('/').ok_or(format!(
+        "`{}` is not a GitHub repository",
+        updated.configured_project
+    ))?;
+
+    {
+        let configured_project = updated.configured_project.clone();
+        let branch = branch.clone();
+        let actor = actor.clone();
+
+        ::rocket::tokio::task::spawn_blocking(move || {
+            git::push_branch(settings, &configured_project, &branch, &contents, &actor)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.message().to_string())?;
+    }
+
+    let pull_request = octocrab::instance()
+        .pulls(owner, repo)
+        .create(
+            format!("Config change from {}", actor.name),
+            &branch,
+            &settings.branch,
+        )
+        .body(format!(
+            "Requested by {} ({}) in Slack.\n\n{}\n\nctrl reloads its config when this is merged.",
+            actor.name,
+            actor.slack_id,
+            describe_changes(current, updated)
+                .iter()
+                .map(|change| format!("- {}", change))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    println!("Opened config pull request #{}", pull_request.number);
+
+    Ok(pull_request
+        .html_url
+        .map(|url| url.to_string())
+        .unwrap_or(format!(
+            "https://github.com/{}/pull/{}",
+            updated.configured_project, pull_request.number
+        )))
+}
+
+/// A line for each project, profile or setting that differs between the manifests.
+fn describe_changes(current: &Manifest, updated: &Manifest) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    if current.configured_project != updated.configured_project {
+        changes.push(format!(
+            "Changed the config repository to `{}`",
+            updated.configured_project
+        ));
+    }
+
+    if current.managers != updated.managers {
+        changes.push("Changed the managers".to_string());
+    }
+
+    let project_names = current
+        .projects
+        .keys()
+        .chain(updated.projects.keys())
+        .collect::<BTreeSet<&String>>();
+
+    for project_name in project_names {
+        match (
+            current.projects.get(project_name),
+            updated.projects.get(project_name),
+        ) {
+            (None, Some(_)) => changes.push(format!("Created project `{}`", project_name)),
+            (Some(_), None) => changes.push(format!("Deleted project `{}`", project_name)),
+            (Some(before), Some(after)) if before != after => {
+                changes.push(format!("Changed project `{}`", project_name))
+            }
+            _ => (),
+        }
+    }
+
+    let slack_ids = current
+        .profiles
+        .keys()
+        .chain(updated.profiles.keys())
+        .collect::<BTreeSet<&String>>();
+
+    for slack_id in slack_ids {
+        if current.profiles.get(slack_id) != updated.profiles.get(slack_id) {
+            changes.push(format!("Changed the profile of Slack user `{}`", slack_id));
+        }
+    }
+
+    changes
+}
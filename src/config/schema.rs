@@ -0,0 +1,263 @@
+use std::fmt;
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use super::{
+    validate::{self, Severity, ValidationError},
+    Manifest,
+};
+
+/// The version of the manifest schema this build reads and writes.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Upgrades a manifest from the version at its index to the next one.
+const MIGRATIONS: &[fn(&mut Table)] = &[migrate_v0_to_v1];
+
+/// Manifests from before `version` existed could leave out the tables that were
+/// empty.
+fn migrate_v0_to_v1(manifest: &mut Table) {
+    for table in ["projects", "profiles"] {
+        manifest
+            .entry(table)
+            .or_insert_with(|| Value::Table(Table::new()));
+    }
+
+    manifest
+        .entry("managers")
+        .or_insert_with(|| Value::Array(vec![]));
+}
+
+/// Why a manifest couldn't be loaded or saved.
+#[derive(Debug, Clone)]
+pub enum ManifestError {
+    /// The manifest couldn't be read from the store.
+    Read(String),
+    /// The manifest couldn't be written to the store.
+    Write(String),
+    /// The manifest isn't valid TOML or doesn't match the schema.
+    Syntax {
+        message: String,
+        /// The line and column of the mistake, counting from 1.
+        position: Option<(usize, usize)>,
+    },
+    /// The manifest was written by a newer version of ctrl.
+    UnsupportedVersion(u32),
+    /// The manifest parsed, but some of its settings can't be used.
+    Invalid(Vec<ValidationError>),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Read(message) | ManifestError::Write(message) => {
+                write!(f, "{}", message)
+            }
+            ManifestError::Syntax {
+                message,
+                position: Some((line, column)),
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+            ManifestError::Syntax { message, .. } => write!(f, "{}", message),
+            ManifestError::UnsupportedVersion(version) => write!(
+                f,
+                "version {} is newer than the {} this ctrl supports",
+                version, CURRENT_VERSION
+            ),
+            ManifestError::Invalid(problems) => write!(
+                f,
+                "{}",
+                problems
+                    .iter()
+                    .map(|problem| problem.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+        }
+    }
+}
+
+impl ManifestError {
+    fn syntax(source: &str, e: toml::de::Error) -> ManifestError {
+        ManifestError::Syntax {
+            message: e.message().to_string(),
+            position: e.span().map(|span| validate::position(source, span.start)),
+        }
+    }
+}
+
+/// Parses a manifest, migrating it from older schema versions and validating it.
+/// Warnings are logged; errors fail the parse.
+pub fn parse(source: &str) -> Result<Manifest, ManifestError> {
+    let mut table =
+        toml::from_str::<Table>(source).map_err(|e| ManifestError::syntax(source, e))?;
+
+    let version = match table.get("version") {
+        None => 0,
+        Some(Value::Integer(version)) if *version >= 0 => *version as u32,
+        Some(_) => {
+            return Err(ManifestError::Syntax {
+                message: "`version` must be a whole number".to_string(),
+                position: None,
+            })
+        }
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(ManifestError::UnsupportedVersion(version));
+    }
+
+    let manifest = if version == CURRENT_VERSION {
+        // Parse the source itself so mistakes are reported with their position
+        toml::from_str::<Manifest>(source).map_err(|e| ManifestError::syntax(source, e))?
+    } else {
+        for migration in &MIGRATIONS[version as usize..] {
+            migration(&mut table);
+        }
+        println!(
+            "Migrated manifest from version {} to {}",
+            version, CURRENT_VERSION
+        );
+
+        table.insert(
+            "version".to_string(),
+            Value::Integer(CURRENT_VERSION.into()),
+        );
+        Manifest::deserialize(Value::Table(table)).map_err(|e| ManifestError::Syntax {
+            message: e.message().to_string(),
+            position: None,
+        })?
+    };
+
+    check(manifest, Some(source))
+}
+
+/// Validates a manifest, locating problems in `source` when it was read from a
+/// file. Warnings are logged; errors fail the check.
+pub fn check(manifest: Manifest, source: Option<&str>) -> Result<Manifest, ManifestError> {
+    let mut problems = validate::validate(&manifest);
+    if let Some(source) = source {
+        validate::locate(source, &mut problems);
+    }
+
+    for warning in problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Warning)
+    {
+        println!("Manifest {}", warning);
+    }
+
+    problems.retain(|problem| problem.severity == Severity::Error);
+    if !problems.is_empty() {
+        return Err(ManifestError::Invalid(problems));
+    }
+
+    Ok(manifest)
+}
+
+/// Fails with the errors in `manifest` that would stop it from loading, so changes
+/// can't save a manifest ctrl would refuse to start with. Warnings are ignored.
+pub fn ensure_valid(manifest: &Manifest) -> Result<(), ManifestError> {
+    let errors = validate::validate(manifest)
+        .into_iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .collect::<Vec<_>>();
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(ManifestError::Invalid(errors)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = r#"
+[projects.ctrl]
+slack_channel = "C1"
+github_repo = "octo/ctrl"
+project_owners = ["octocat"]
+
+[profiles.U1]
+github_username = "octocat"
+"#;
+
+    fn current(rest: &str) -> String {
+        format!(
+            "version = 1\nmanagers = []\nconfigured_project = \"ctrl\"\n{}",
+            rest
+        )
+    }
+
+    #[test]
+    fn parses_the_current_version() {
+        let manifest = parse(&current(PROJECT)).unwrap();
+
+        assert_eq!(manifest.version, CURRENT_VERSION);
+        assert_eq!(
+            manifest.projects["ctrl"].github_repo.as_deref(),
+            Some("octo/ctrl")
+        );
+    }
+
+    #[test]
+    fn migrates_manifests_without_a_version() {
+        let manifest = parse("configured_project = \"ctrl\"\n").unwrap();
+
+        assert_eq!(manifest.version, CURRENT_VERSION);
+        assert!(manifest.projects.is_empty());
+        assert!(manifest.profiles.is_empty());
+        assert!(manifest.managers.is_empty());
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        assert!(matches!(
+            parse("version = 2\nconfigured_project = \"ctrl\"\n"),
+            Err(ManifestError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_their_position() {
+        match parse(&current("[projects.ctrl]\nslack_channel = C1\n")) {
+            Err(ManifestError::Syntax {
+                position: Some((line, _)),
+                ..
+            }) => assert_eq!(line, 5),
+            other => panic!("expected a syntax error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn locates_invalid_settings() {
+        let source = current(&PROJECT.replace("octo/ctrl", "not a repo"));
+
+        match parse(&source) {
+            Err(ManifestError::Invalid(problems)) => {
+                assert_eq!(problems.len(), 1);
+                assert_eq!(problems[0].path, "projects.ctrl.github_repo");
+                assert_eq!(problems[0].position, Some((7, 15)));
+            }
+            other => panic!("expected invalid settings, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn ensure_valid_ignores_warnings() {
+        let mut manifest = parse(&current(PROJECT)).unwrap();
+        manifest.profiles.clear();
+        assert!(ensure_valid(&manifest).is_ok());
+
+        manifest
+            .projects
+            .get_mut("ctrl")
+            .unwrap()
+            .merge
+            .required_approvals = 0;
+        assert!(matches!(
+            ensure_valid(&manifest),
+            Err(ManifestError::Invalid(problems)) if problems.len() == 1
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::HashMap,
+    fs,
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const STATE_PATH: &str = "state.toml";
+
+/// Bookkeeping that ctrl needs between runs. Unlike the manifest this is local to
+/// the deployment, so it is kept in `state.toml` and never committed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct State {
+    /// When each project's digest was last posted, by project name.
+    #[serde(default)]
+    pub last_digests: HashMap<String, DateTime<Utc>>,
+    /// Approved pull requests waiting on CI, by `owner/repo#number`.
+    #[serde(default)]
+    pub pending_merges: HashMap<String, PendingMerge>,
+    /// The last reviewer picked by the round-robin strategy, by repository.
+    #[serde(default)]
+    pub last_reviewers: HashMap<String, String>,
+    /// Until when reminders are snoozed for a pull request, by `owner/repo#number`.
+    #[serde(default)]
+    pub snoozed: HashMap<String, DateTime<Utc>>,
+    /// The Slack thread of each pull request, by `owner/repo#number`.
+    #[serde(default)]
+    pub pull_request_threads: HashMap<String, PullRequestThread>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingMerge {
+    pub repo: String,
+    pub number: u64,
+    /// The commit the checks are running on.
+    pub head_sha: String,
+    /// Who approved the pull request, credited in the merge commit.
+    pub approver: String,
+}
+
+/// A pull request's Slack thread, whose first message shows its current status.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PullRequestThread {
+    pub channel_id: String,
+    /// The `ts` of the first message, which later messages reply to.
+    pub ts: String,
+    /// The text of the first message, kept to rebuild it when the status changes.
+    pub text: String,
+    pub status: String,
+    /// Who ctrl requested reviews from, whose review requests aren't announced again.
+    #[serde(default)]
+    pub reviewers: Vec<String>,
+    /// When the pull request was closed. Closed threads are forgotten after a while.
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+/// The key of a pull request in the state, e.g. `owner/repo#12`.
+pub fn pull_request_key(repo: &str, number: u64) -> String {
+    format!("{}#{}", repo, number)
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| {
+        if !Path::new(STATE_PATH).exists() {
+            return Default::default();
+        }
+
+        // Starting over with an empty state would forget pending merges and threads
+        let state = fs::read_to_string(STATE_PATH)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| toml::from_str(&contents).map_err(|e| e.to_string()));
+        match state {
+            Ok(state) => Mutex::new(state),
+            Err(e) => panic!("Failed to read {}: {}", STATE_PATH, e),
+        }
+    })
+}
+
+/// Loads the state from `state.toml`. Later reads and updates use the copy in memory.
+pub fn load_state() {
+    state();
+}
+
+/// A snapshot of the state. Changes to it are not saved, use `update_state` instead.
+pub fn read_state() -> State {
+    state().lock().unwrap().clone()
+}
+
+/// Applies `update` to the state and writes it to `state.toml`, holding the lock
+/// throughout so concurrent updates can't overwrite each other.
+pub fn update_state<T>(update: impl FnOnce(&mut State) -> T) -> T {
+    let mut state = state().lock().unwrap();
+    let value = update(&mut state);
+
+    if let Err(e) = write_state(&state) {
+        println!("Failed to write {}: {}", STATE_PATH, e);
+    }
+
+    value
+}
+
+/// Writes a copy and renames it over `state.toml` so it is never left half written.
+fn write_state(state: &State) -> Result<(), String> {
+    let contents = toml::to_string_pretty(state).map_err(|e| e.to_string())?;
+
+    let temp_path = Path::new(STATE_PATH).with_extension("toml.tmp");
+    let mut file = File::create(&temp_path).map_err(|e| e.to_string())?;
+    file.write_all(contents.as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|e| e.to_string())?;
+    drop(file);
+
+    fs::rename(&temp_path, STATE_PATH).map_err(|e| e.to_string())
+}
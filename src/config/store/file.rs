@@ -0,0 +1,73 @@
+use std::{
+    env, fs,
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use super::ManifestStore;
+use crate::config::{git, schema, schema::ManifestError, Actor, Manifest};
+
+/// Keeps the manifest in a TOML file, committed and pushed to the config repository
+/// in the background after every save unless `GITHUB_PUSH_DISABLE=1`.
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: impl Into<PathBuf>) -> FileStore {
+        FileStore { path: path.into() }
+    }
+}
+
+impl ManifestStore for FileStore {
+    fn load(&self) -> Result<Manifest, ManifestError> {
+        // A missing file more likely means a wrong CONFIG_REPO_PATH than a new
+        // deployment, and saving an empty manifest would push it over the real one
+        if !self.path.exists() {
+            return Err(ManifestError::Read(format!(
+                "{} does not exist, check CONFIG_REPO_PATH",
+                self.path.display()
+            )));
+        }
+
+        let mut file = File::open(&self.path).map_err(|e| ManifestError::Read(e.to_string()))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .map_err(|e| ManifestError::Read(e.to_string()))?;
+
+        drop(file);
+
+        let manifest = schema::parse(&contents)?;
+
+        println!("Read {}", self.path.display());
+        Ok(manifest)
+    }
+
+    fn save(&self, manifest: &Manifest, actor: &Actor) -> Result<(), ManifestError> {
+        let write_error = |e: &dyn std::fmt::Display| {
+            ManifestError::Write(format!("{}: {}", self.path.display(), e))
+        };
+
+        let manifest_toml = toml::to_string_pretty(&manifest).map_err(|e| write_error(&e))?;
+
+        // Write a copy and rename it over the manifest so it is never left half written
+        let temp_path = self.path.with_extension("toml.tmp");
+        let mut file = File::create(&temp_path).map_err(|e| write_error(&e))?;
+        file.write_all(manifest_toml.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| write_error(&e))?;
+        drop(file);
+
+        fs::rename(&temp_path, &self.path).map_err(|e| write_error(&e))?;
+
+        println!("Wrote {}", self.path.display());
+
+        let disabled = env::var("GITHUB_PUSH_DISABLE").unwrap_or("0".to_string());
+        if disabled != "1" {
+            git::queue_push(&manifest.configured_project, actor);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use std::sync::Mutex;
+
+use super::ManifestStore;
+use crate::config::{schema::ManifestError, Actor, Manifest};
+
+/// Keeps the manifest in memory only, for tests and throwaway deployments.
+#[derive(Default)]
+pub struct MemoryStore {
+    manifest: Mutex<Manifest>,
+}
+
+impl ManifestStore for MemoryStore {
+    fn load(&self) -> Result<Manifest, ManifestError> {
+        Ok(self.manifest.lock().unwrap().clone())
+    }
+
+    fn save(&self, manifest: &Manifest, _actor: &Actor) -> Result<(), ManifestError> {
+        *self.manifest.lock().unwrap() = manifest.clone();
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use std::{env, sync::OnceLock};
+
+use super::{git, schema::ManifestError, Actor, Manifest};
+
+pub mod file;
+pub mod memory;
+pub mod sqlite;
+
+pub use self::{file::FileStore, memory::MemoryStore, sqlite::SqliteStore};
+
+/// Where the manifest is kept.
+pub trait ManifestStore: Send + Sync {
+    /// Reads the whole manifest, failing rather than falling back to a default when
+    /// what is stored can't be used.
+    fn load(&self) -> Result<Manifest, ManifestError>;
+
+    /// Stores `manifest`, replacing what was there before. `actor` made the change.
+    /// On failure what was stored before is left in place.
+    fn save(&self, manifest: &Manifest, actor: &Actor) -> Result<(), ManifestError>;
+}
+
+static STORE: OnceLock<Box<dyn ManifestStore>> = OnceLock::new();
+
+/// The store chosen by `MANIFEST_STORE`: `file` (the default) for `manifest.toml`
+/// pushed to the config repository, `sqlite` for the database at `MANIFEST_DATABASE`,
+/// or `memory` for a manifest that only lives as long as the process.
+pub fn store() -> &'static dyn ManifestStore {
+    STORE
+        .get_or_init(|| {
+            dotenv::dotenv().ok();
+
+            let kind = env::var("MANIFEST_STORE").unwrap_or("file".to_string());
+            println!("Using {} manifest store", kind);
+
+            match kind.as_str() {
+                "file" => Box::new(FileStore::new(git::settings().manifest_path())),
+                "sqlite" => Box::new(SqliteStore::open(
+                    &env::var("MANIFEST_DATABASE").unwrap_or("manifest.db".to_string()),
+                )),
+                "memory" => Box::new(MemoryStore::default()),
+                _ => panic!("Unknown MANIFEST_STORE: {}", kind),
+            }
+        })
+        .as_ref()
+}
@@ -0,0 +1,198 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use rocket::serde::json::serde_json;
+use rusqlite::{params, Connection, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::ManifestStore;
+use crate::config::{
+    schema::{self, ManifestError, CURRENT_VERSION},
+    Actor, Manifest,
+};
+
+/// Keeps the manifest in a SQLite database with a row per project and profile.
+/// Saves run in a transaction and only touch the rows that changed.
+pub struct SqliteStore {
+    path: String,
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> SqliteStore {
+        let connection = Connection::open(path).expect("Failed to open manifest database");
+
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+                CREATE TABLE IF NOT EXISTS managers (position INTEGER PRIMARY KEY, github_username TEXT NOT NULL);
+                CREATE TABLE IF NOT EXISTS projects (name TEXT PRIMARY KEY, data TEXT NOT NULL);
+                CREATE TABLE IF NOT EXISTS profiles (slack_id TEXT PRIMARY KEY, data TEXT NOT NULL);",
+            )
+            .expect("Failed to create manifest tables");
+
+        println!("Opened manifest database {}", path);
+
+        SqliteStore {
+            path: path.to_string(),
+            connection: Mutex::new(connection),
+        }
+    }
+}
+
+impl ManifestStore for SqliteStore {
+    fn load(&self) -> Result<Manifest, ManifestError> {
+        let connection = self.connection.lock().unwrap();
+        let mut manifest = Manifest::default();
+
+        let settings = read_rows::<serde_json::Value>(&connection, "settings")?;
+
+        // Every save writes the version, so a database without one was never saved
+        // to, most likely because MANIFEST_DATABASE points somewhere new. Saving an
+        // empty manifest there would lose every project.
+        let version = match settings.get("version") {
+            Some(version) => version.as_u64().ok_or_else(|| {
+                ManifestError::Read(format!(
+                    "{}: version `{}` is not a number",
+                    self.path, version
+                ))
+            })? as u32,
+            None => {
+                return Err(ManifestError::Read(format!(
+                    "{} has no manifest, check MANIFEST_DATABASE",
+                    self.path
+                )))
+            }
+        };
+
+        // Rows are read into the current types, so older versions need no migration
+        if version > CURRENT_VERSION {
+            return Err(ManifestError::UnsupportedVersion(version));
+        }
+
+        if let Some(configured_project) = settings
+            .get("configured_project")
+            .and_then(|configured_project| configured_project.as_str())
+        {
+            manifest.configured_project = configured_project.to_string();
+        }
+
+        manifest.managers = connection
+            .prepare("SELECT github_username FROM managers ORDER BY position")
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| row.get(0))?
+                    .collect::<Result<Vec<String>, _>>()
+            })
+            .map_err(|e| ManifestError::Read(e.to_string()))?;
+
+        manifest.projects = read_rows(&connection, "projects")?;
+        manifest.profiles = read_rows(&connection, "profiles")?;
+
+        schema::check(manifest, None)
+    }
+
+    fn save(&self, manifest: &Manifest, _actor: &Actor) -> Result<(), ManifestError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(write_error)?;
+
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('version', ?1), ('configured_project', ?2)",
+                params![
+                    serde_json::to_string(&manifest.version).map_err(write_error)?,
+                    serde_json::to_string(&manifest.configured_project).map_err(write_error)?
+                ],
+            )
+            .map_err(write_error)?;
+
+        transaction
+            .execute("DELETE FROM managers", [])
+            .map_err(write_error)?;
+        for (position, manager) in manifest.managers.iter().enumerate() {
+            transaction
+                .execute(
+                    "INSERT INTO managers (position, github_username) VALUES (?1, ?2)",
+                    params![position, manager],
+                )
+                .map_err(write_error)?;
+        }
+
+        write_rows(&transaction, "projects", "name", &manifest.projects)?;
+        write_rows(&transaction, "profiles", "slack_id", &manifest.profiles)?;
+
+        // Dropping the transaction on an error above rolls it back
+        transaction.commit().map_err(write_error)?;
+
+        println!("Wrote manifest database");
+        Ok(())
+    }
+}
+
+fn write_error(e: impl std::fmt::Display) -> ManifestError {
+    ManifestError::Write(format!("manifest database: {}", e))
+}
+
+/// Reads a key/JSON table, failing on rows that no longer deserialize rather than
+/// dropping them.
+fn read_rows<T: DeserializeOwned>(
+    connection: &Connection,
+    table: &str,
+) -> Result<HashMap<String, T>, ManifestError> {
+    connection
+        .prepare(&format!("SELECT * FROM {}", table))
+        .and_then(|mut statement| {
+            statement
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| ManifestError::Read(e.to_string()))?
+        .into_iter()
+        .map(|(key, data)| match serde_json::from_str(&data) {
+            Ok(value) => Ok((key, value)),
+            Err(e) => Err(ManifestError::Syntax {
+                message: format!("{} row `{}`: {}", table, key, e),
+                position: None,
+            }),
+        })
+        .collect()
+}
+
+/// Brings a key/JSON table in line with `rows`, writing only what changed.
+fn write_rows<T: Serialize>(
+    transaction: &Transaction,
+    table: &str,
+    key_column: &str,
+    rows: &HashMap<String, T>,
+) -> Result<(), ManifestError> {
+    let existing = read_rows::<serde_json::Value>(transaction, table)
+        .map_err(|e| ManifestError::Write(e.to_string()))?;
+
+    for (key, value) in rows {
+        let value = serde_json::to_value(value).map_err(write_error)?;
+
+        if existing.get(key) != Some(&value) {
+            transaction
+                .execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO {} ({}, data) VALUES (?1, ?2)",
+                        table, key_column
+                    ),
+                    params![key, value.to_string()],
+                )
+                .map_err(write_error)?;
+        }
+    }
+
+    for key in existing.keys().filter(|key| !rows.contains_key(*key)) {
+        transaction
+            .execute(
+                &format!("DELETE FROM {} WHERE {} = ?1", table, key_column),
+                params![key],
+            )
+            .map_err(write_error)?;
+    }
+
+    Ok(())
+}
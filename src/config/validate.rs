@@ -0,0 +1,303 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
+
+use chrono_tz::Tz;
+use serde::Deserialize;
+use toml::Spanned;
+
+use super::{get_user_by_github_username, Manifest, ReviewerStrategy};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The manifest can't be used as it is.
+    Error,
+    /// Worth fixing, but the manifest still works.
+    Warning,
+}
+
+/// A problem with a setting in the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub severity: Severity,
+    /// The setting at fault, e.g. `projects.ctrl.github_repo`.
+    pub path: String,
+    pub message: String,
+    /// The line and column of the setting, counting from 1, when read from a file.
+    pub position: Option<(usize, usize)>,
+}
+
+impl ValidationError {
+    fn error(path: String, message: String) -> ValidationError {
+        ValidationError {
+            severity: Severity::Error,
+            path,
+            message,
+            position: None,
+        }
+    }
+
+    fn warning(path: String, message: String) -> ValidationError {
+        ValidationError {
+            severity: Severity::Warning,
+            ..ValidationError::error(path, message)
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((line, column)) = self.position {
+            write!(f, "line {}, column {}: ", line, column)?;
+        }
+        if self.severity == Severity::Warning {
+            write!(f, "warning: ")?;
+        }
+
+        write!(f, "`{}`: {}", self.path, self.message)
+    }
+}
+
+/// Checks the settings that parse but can't be used, returning a problem for each
+/// one found. Only problems with `Severity::Error` stop the manifest being loaded.
+pub fn validate(manifest: &Manifest) -> Vec<ValidationError> {
+    let mut problems = Vec::new();
+
+    let projects = manifest.projects.iter().collect::<BTreeMap<_, _>>();
+    let mut channels = HashMap::new();
+
+    for (project_name, project) in projects {
+        let path = |field: &str| format!("projects.{}.{}", project_name, field);
+
+        if let Some(other) = channels.insert(&project.slack_channel, project_name) {
+            problems.push(ValidationError::error(
+                path("slack_channel"),
+                format!(
+                    "channel `{}` is already used by project `{}`",
+                    project.slack_channel, other
+                ),
+            ));
+        }
+
+        if let Some(github_repo) = &project.github_repo {
+            if !is_repo_slug(github_repo) {
+                problems.push(ValidationError::error(
+                    path("github_repo"),
+                    format!("`{}` is not an `owner/repo` slug", github_repo),
+                ));
+            }
+        }
+
+        for owner in &project.project_owners {
+            if get_user_by_github_username(manifest, owner).is_none() {
+                problems.push(ValidationError::warning(
+                    path("project_owners"),
+                    format!("`{}` has no profile, so can't be mentioned in Slack", owner),
+                ));
+            }
+        }
+
+        if let Some(reminders) = &project.reminders {
+            if let Err(e) = cron::Schedule::from_str(&reminders.schedule) {
+                problems.push(ValidationError::error(
+                    path("reminders"),
+                    format!("invalid schedule `{}`: {}", reminders.schedule, e),
+                ));
+            }
+        }
+
+        if let Some(digest) = &project.digest {
+            if let Err(e) = cron::Schedule::from_str(&digest.schedule) {
+                problems.push(ValidationError::error(
+                    path("digest"),
+                    format!("invalid schedule `{}`: {}", digest.schedule, e),
+                ));
+            }
+
+            if let Some(timezone) = &digest.timezone {
+                if timezone.parse::<Tz>().is_err() {
+                    problems.push(ValidationError::error(
+                        path("digest"),
+                        format!("unknown timezone `{}`", timezone),
+                    ));
+                }
+            }
+        }
+
+        for rule in &project.branch_policy {
+            if glob::Pattern::new(&rule.pattern).is_err() {
+                problems.push(ValidationError::error(
+                    path("branch_policy"),
+                    format!("invalid branch pattern `{}`", rule.pattern),
+                ));
+            }
+        }
+
+        if project.merge.required_approvals == 0 {
+            problems.push(ValidationError::error(
+                path("merge"),
+                "`required_approvals` must be at least 1".to_string(),
+            ));
+        }
+
+        match project.reviewers {
+            ReviewerStrategy::Random { count: 0 }
+            | ReviewerStrategy::RoundRobin { count: 0 }
+            | ReviewerStrategy::LeastLoaded { count: 0 } => problems.push(ValidationError::error(
+                path("reviewers"),
+                "`count` must be at least 1".to_string(),
+            )),
+            _ => (),
+        }
+    }
+
+    problems
+}
+
+/// Whether `repo` looks like a GitHub `owner/repo` slug.
+pub fn is_repo_slug(repo: &str) -> bool {
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    };
+
+    matches!(repo.split_once('/'), Some((owner, name)) if valid(owner) && valid(name))
+}
+
+/// The settings of each project and profile, with where they are in the file.
+#[derive(Deserialize, Default)]
+struct Locations {
+    #[serde(default)]
+    projects: HashMap<String, HashMap<String, Spanned<toml::Value>>>,
+    #[serde(default)]
+    profiles: HashMap<String, HashMap<String, Spanned<toml::Value>>>,
+}
+
+/// Fills in the position of each problem's setting in `source`.
+pub fn locate(source: &str, problems: &mut [ValidationError]) {
+    let locations = toml::from_str::<Locations>(source).unwrap_or_default();
+
+    for problem in problems {
+        let span = problem
+            .path
+            .rsplit_once('.')
+            .and_then(|(table, field)| {
+                let (table, name) = table.split_once('.')?;
+                match table {
+                    "projects" => locations.projects.get(name),
+                    "profiles" => locations.profiles.get(name),
+                    _ => None,
+                }?
+                .get(field)
+            })
+            .map(|value| value.span());
+
+        problem.position = span.map(|span| position(source, span.start));
+    }
+}
+
+/// The line and column of byte `offset` in `source`, counting from 1.
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{branches::BranchRule, Digest, Project, Reminders};
+
+    fn manifest(projects: Vec<(&str, Project)>) -> Manifest {
+        Manifest {
+            version: 1,
+            projects: projects
+                .into_iter()
+                .map(|(name, project)| (name.to_string(), project))
+                .collect(),
+            managers: vec![],
+            configured_project: "ctrl".to_string(),
+            profiles: HashMap::new(),
+        }
+    }
+
+    fn paths(problems: &[ValidationError]) -> Vec<&str> {
+        problems
+            .iter()
+            .map(|problem| problem.path.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn accepts_repo_slugs() {
+        assert!(is_repo_slug("octo/ctrl"));
+        assert!(is_repo_slug("octo-org/ctrl.rs"));
+        assert!(!is_repo_slug("ctrl"));
+        assert!(!is_repo_slug("octo/"));
+        assert!(!is_repo_slug("https://github.com/octo/ctrl"));
+    }
+
+    #[test]
+    fn reports_shared_channels() {
+        let project = Project {
+            slack_channel: "C1".to_string(),
+            ..Project::default()
+        };
+        let problems = validate(&manifest(vec![("a", project.clone()), ("b", project)]));
+
+        assert_eq!(paths(&problems), vec!["projects.b.slack_channel"]);
+        assert_eq!(problems[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn reports_unusable_settings() {
+        let project = Project {
+            reminders: Some(Reminders {
+                schedule: "every morning".to_string(),
+                stale_after_hours: 24,
+            }),
+            digest: Some(Digest {
+                schedule: "0 0 9 * * *".to_string(),
+                timezone: Some("Mars/Olympus".to_string()),
+            }),
+            branch_policy: vec![BranchRule::new("release/[", true, false)],
+            reviewers: ReviewerStrategy::Random { count: 0 },
+            ..Project::default()
+        };
+        let problems = validate(&manifest(vec![("a", project)]));
+
+        assert_eq!(
+            paths(&problems),
+            vec![
+                "projects.a.reminders",
+                "projects.a.digest",
+                "projects.a.branch_policy",
+                "projects.a.reviewers",
+            ]
+        );
+    }
+
+    #[test]
+    fn owners_without_profiles_are_warnings() {
+        let project = Project {
+            project_owners: vec!["octocat".to_string()],
+            ..Project::default()
+        };
+        let problems = validate(&manifest(vec![("a", project)]));
+
+        assert_eq!(paths(&problems), vec!["projects.a.project_owners"]);
+        assert_eq!(problems[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn positions_count_from_one() {
+        assert_eq!(position("a = 1\nbc = 2", 0), (1, 1));
+        assert_eq!(position("a = 1\nbc = 2", 9), (2, 4));
+    }
+}
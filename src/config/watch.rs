@@ -0,0 +1,57 @@
+use std::{env, fs, time::Duration};
+
+use super::{git, reload_manifest, Actor};
+use crate::error::{self, Error};
+
+/// Reloads the manifest from the store, reporting why to the admin channel when the
+/// new manifest is rejected and the previous one is kept. `source` says what changed
+/// it, e.g. `a push to a/b`.
+pub async fn reload(source: &str) {
+    let actor = Actor {
+        name: format!("ctrl after {}", source),
+        ..Actor::ctrl()
+    };
+
+    match reload_manifest(&actor).await {
+        Ok(true) => println!("Reloaded the manifest after {}", source),
+        Ok(false) => (),
+        Err(e) => {
+            error::report(
+                &Error::Config(e),
+                &format!("Reloading the manifest after {}", source),
+                None,
+            )
+            .await
+        }
+    }
+}
+
+/// Reloads the manifest whenever `manifest.toml` changes on disk, when
+/// `MANIFEST_WATCH=1`.
+pub async fn watch() {
+    dotenv::dotenv().ok();
+
+    if env::var("MANIFEST_WATCH").unwrap_or("0".to_string()) != "1" {
+        return;
+    }
+
+    let path = git::settings().manifest_path();
+    let modified = || {
+        fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let mut last_modified = modified();
+
+    println!("Watching {} for changes", path.display());
+
+    loop {
+        ::rocket::tokio::time::sleep(Duration::from_secs(5)).await;
+
+        let current = modified();
+        if current != last_modified {
+            last_modified = current;
+            reload(&format!("an edit to {}", path.display())).await;
+        }
+    }
+}
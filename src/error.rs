@@ -0,0 +1,117 @@
+use std::{env, fmt};
+
+use rocket::serde::json::Value;
+
+use crate::{config::schema::ManifestError, slack::handler::respond_http_text};
+
+/// Notices posted to Slack are cut short after this many characters.
+const MAX_NOTICE_LENGTH: usize = 500;
+
+/// Everything that can go wrong while handling a webhook or command.
+#[derive(Debug)]
+pub enum Error {
+    /// A GitHub API call failed.
+    GitHub(octocrab::Error),
+    /// A Slack API call failed, or Slack isn't configured.
+    Slack(String),
+    /// An HTTP request failed, such as a Jira API call or a reply to a Slack
+    /// `response_url`.
+    Http(reqwest::Error),
+    /// The manifest couldn't be loaded.
+    Config(ManifestError),
+    /// Syncing the config repository failed.
+    Git(git2::Error),
+    /// A webhook payload is missing a field, or it has the wrong type.
+    Payload(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::GitHub(octocrab::Error::GitHub { source, .. }) => {
+                write!(f, "GitHub error: {}", source)
+            }
+            Error::GitHub(e) => write!(f, "GitHub error: {}", e),
+            Error::Slack(e) => write!(f, "Slack error: {}", e),
+            Error::Http(e) => write!(f, "HTTP error: {}", e),
+            Error::Config(e) => write!(f, "Config error: {}", e),
+            Error::Git(e) => write!(f, "Git error: {}", e.message()),
+            Error::Payload(e) => write!(f, "Unexpected payload: {}", e),
+        }
+    }
+}
+
+impl From<octocrab::Error> for Error {
+    fn from(e: octocrab::Error) -> Self {
+        Error::GitHub(e)
+    }
+}
+
+impl From<slack_rust::error::Error> for Error {
+    fn from(e: slack_rust::error::Error) -> Self {
+        Error::Slack(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<ManifestError> for Error {
+    fn from(e: ManifestError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Self {
+        Error::Git(e)
+    }
+}
+
+/// Reads fields of a webhook payload by JSON pointer, e.g. `/pull_request/number`.
+pub trait Payload {
+    fn str_at(&self, pointer: &str) -> Result<&str>;
+    fn u64_at(&self, pointer: &str) -> Result<u64>;
+}
+
+impl Payload for Value {
+    fn str_at(&self, pointer: &str) -> Result<&str> {
+        self.pointer(pointer)
+            .and_then(Value::as_str)
+            .ok_or_else(|| Error::Payload(format!("expected a string at `{}`", pointer)))
+    }
+
+    fn u64_at(&self, pointer: &str) -> Result<u64> {
+        self.pointer(pointer)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| Error::Payload(format!("expected a number at `{}`", pointer)))
+    }
+}
+
+/// Logs a failure with what was being done, and posts a short notice to `channel`
+/// or, when there is no channel to tell, to `SLACK_ADMIN_CHANNEL` if set.
+pub async fn report(error: &Error, context: &str, channel: Option<&str>) {
+    println!("{} failed: {}", context, error);
+
+    let channel = match channel
+        .map(String::from)
+        .or(env::var("SLACK_ADMIN_CHANNEL").ok())
+    {
+        Some(channel) => channel,
+        None => return,
+    };
+
+    let mut notice = format!("⚠️ {} failed: {}", context, error);
+    if notice.chars().count() > MAX_NOTICE_LENGTH {
+        notice = notice.chars().take(MAX_NOTICE_LENGTH).collect::<String>() + "…";
+    }
+
+    if let Err(e) = respond_http_text(&channel, notice).await {
+        println!("Failed to report the failure to Slack: {}", e);
+    }
+}
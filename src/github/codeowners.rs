@@ -0,0 +1,207 @@
+use glob::{MatchOptions, Pattern};
+
+/// Where GitHub looks for a CODEOWNERS file, in the order it checks them.
+const LOCATIONS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// A line of a CODEOWNERS file.
+#[derive(Debug)]
+pub struct Rule {
+    pub pattern: String,
+    pub owners: Vec<String>,
+}
+
+/// The owners of a pull request's changed files, split the way the review request
+/// API expects them.
+#[derive(Debug, Default)]
+pub struct CodeOwners {
+    /// GitHub usernames, without the `@`.
+    pub users: Vec<String>,
+    /// Teams as `org/team`, without the `@`.
+    pub teams: Vec<String>,
+}
+
+pub fn parse(contents: &str) -> Vec<Rule> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let pattern = parts.next()?.to_string();
+
+            Some(Rule {
+                pattern,
+                owners: parts.map(String::from).collect(),
+            })
+        })
+        .collect()
+}
+
+/// Whether a CODEOWNERS pattern matches `path`, following the gitignore rules
+/// GitHub uses: patterns without a slash match at any depth, a trailing slash only
+/// matches directories, `*` stays within a directory and a directory name matches
+/// everything under it.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let directory_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+
+    let pattern = match anchored {
+        true => pattern.to_string(),
+        false => format!("**/{}", pattern),
+    };
+
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let matches = |pattern: &str| {
+        Pattern::new(pattern)
+            .map(|pattern| pattern.matches_with(path, options))
+            .unwrap_or(false)
+    };
+
+    // Like GitHub, `docs/*` matches files in `docs` but not in its subdirectories
+    let last_segment = pattern.rsplit('/').next().unwrap_or("");
+    let matches_directory = !last_segment.contains('*') && matches(&format!("{}/**", pattern));
+
+    matches_directory || (!directory_only && matches(&pattern))
+}
+
+/// The owners of `path`, from the last matching rule as in GitHub.
+pub fn owners_of<'a>(rules: &'a [Rule], path: &str) -> &'a [String] {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| matches(&rule.pattern, path))
+        .map(|rule| rule.owners.as_slice())
+        .unwrap_or(&[])
+}
+
+/// Reads the CODEOWNERS file on `base` and returns the owners of the files changed
+/// by the pull request. Email owners are skipped as they can't be requested by name.
+pub async fn find_code_owners(repo: &str, number: u64, base: &str) -> octocrab::Result<CodeOwners> {
+    let details = repo.split('/').collect::<Vec<&str>>();
+    let instance = octocrab::instance();
+
+    let mut contents = None;
+    for location in LOCATIONS {
+        let file = instance
+            .repos(details[0], details[1])
+            .get_content()
+            .path(*location)
+            .r#ref(base)
+            .send()
+            .await;
+
+        if let Ok(mut file) = file {
+            contents = file
+                .take_items()
+                .first()
+                .and_then(|file| file.decoded_content());
+            break;
+        }
+    }
+
+    let rules = match contents {
+        Some(contents) => parse(&contents),
+        None => return Ok(CodeOwners::default()),
+    };
+
+    let first_page = instance
+        .pulls(details[0], details[1])
+        .list_files(number)
+        .await?;
+    let files = instance.all_pages(first_page).await?;
+
+    let mut owners = CodeOwners::default();
+    for file in files {
+        for owner in owners_of(&rules, &file.filename) {
+            let owner = match owner.strip_prefix('@') {
+                Some(owner) => owner.to_string(),
+                None => continue,
+            };
+
+            let list = match owner.contains('/') {
+                true => &mut owners.teams,
+                false => &mut owners.users,
+            };
+
+            if !list.contains(&owner) {
+                list.push(owner);
+            }
+        }
+    }
+
+    Ok(owners)
+}
+
+/// The members of a team given as `org/team`.
+pub async fn team_members(team: &str) -> octocrab::Result<Vec<String>> {
+    let (org, slug) = team.split_once('/').unwrap_or((team, ""));
+
+    let members = octocrab::instance()
+        .get::<Vec<octocrab::models::Author>, String, _>(
+            format!("/orgs/{}/teams/{}/members", org, slug),
+            Some(&[("per_page", "100")]),
+        )
+        .await?;
+
+    Ok(members.into_iter().map(|member| member.login).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules_skipping_comments() {
+        let rules = parse(
+            "# Owners\n\n*.rs @octo/rust # the Rust team\n/docs/ @octocat docs@example.com\n",
+        );
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].pattern, "*.rs");
+        assert_eq!(rules[0].owners, vec!["@octo/rust"]);
+        assert_eq!(rules[1].owners, vec!["@octocat", "docs@example.com"]);
+    }
+
+    #[test]
+    fn unanchored_patterns_match_at_any_depth() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/slack/parser.rs"));
+        assert!(matches("build", "tools/build/script.sh"));
+        assert!(!matches("*.rs", "README.md"));
+    }
+
+    #[test]
+    fn anchored_patterns_match_from_the_root() {
+        assert!(matches("/src/*.rs", "src/main.rs"));
+        assert!(matches("src/slack/", "src/slack/parser.rs"));
+        assert!(!matches("/src/*.rs", "tools/src/main.rs"));
+    }
+
+    #[test]
+    fn stars_stay_within_a_directory() {
+        assert!(matches("docs/*", "docs/guide.md"));
+        assert!(!matches("docs/*", "docs/api/index.md"));
+        assert!(matches("docs/**", "docs/api/index.md"));
+    }
+
+    #[test]
+    fn trailing_slashes_only_match_directories() {
+        assert!(matches("logs/", "logs/today.log"));
+        assert!(!matches("logs/", "logs"));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = parse("* @everyone\n*.rs @rustaceans\n/src/config/ @octocat\n");
+
+        assert_eq!(owners_of(&rules, "README.md"), ["@everyone"]);
+        assert_eq!(owners_of(&rules, "src/main.rs"), ["@rustaceans"]);
+        assert_eq!(owners_of(&rules, "src/config/mod.rs"), ["@octocat"]);
+        assert!(owners_of(&[], "src/main.rs").is_empty());
+    }
+}
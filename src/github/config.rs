@@ -0,0 +1,30 @@
+use crate::{
+    config::{git, read_manifest, watch},
+    error::Result,
+};
+
+/// Pulls and reloads the manifest when the config branch of the config repository
+/// is pushed to, e.g. when a config pull request is merged.
+pub async fn handle_push(input: &::rocket::serde::json::Value) -> Result<()> {
+    let configured_project = read_manifest().configured_project;
+    let settings = git::settings();
+
+    if input["repository"]["full_name"].as_str() != Some(configured_project.as_str())
+        || input["ref"].as_str() != Some(format!("refs/heads/{}", settings.branch).as_str())
+    {
+        return Ok(());
+    }
+
+    let source = format!("a push to {}", configured_project);
+    println!(
+        "Config branch pushed to {}, reloading the manifest",
+        input["after"].as_str().unwrap_or("")
+    );
+
+    ::rocket::tokio::task::spawn_blocking(move || git::pull(settings, &configured_project))
+        .await
+        .unwrap_or_else(|e| Err(git2::Error::from_str(&e.to_string()).into()))?;
+
+    watch::reload(&source).await;
+    Ok(())
+}
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+
+use ::rocket::serde::json::Value;
+use octocrab::{models::pulls::ReviewState, params::pulls};
+
+use crate::{
+    config::{
+        get_project_by_github_repo,
+        state::{pull_request_key, read_state, update_state, PendingMerge},
+        MergeMethod, Project,
+    },
+    error,
+    slack::pull_request::{notify, set_status, Event},
+};
+
+/// The combined result of the checks on a commit.
+#[derive(Debug, PartialEq)]
+pub enum ChecksStatus {
+    Passed,
+    /// The checks still running, or required checks not reported yet.
+    Pending(Vec<String>),
+    Failed(Vec<String>),
+}
+
+impl From<MergeMethod> for pulls::MergeMethod {
+    fn from(method: MergeMethod) -> Self {
+        match method {
+            MergeMethod::Merge => pulls::MergeMethod::Merge,
+            MergeMethod::Squash => pulls::MergeMethod::Squash,
+            MergeMethod::Rebase => pulls::MergeMethod::Rebase,
+        }
+    }
+}
+
+/// What a pull request still needs under the project's merge settings before it
+/// can be merged, e.g. `1 more approval`. Only each reviewer's latest approving or
+/// blocking review counts, so a reviewer who approved and then requested changes
+/// is not counted as approving.
+pub async fn missing_approvals(
+    project: &Project,
+    repo: &str,
+    number: u64,
+) -> octocrab::Result<Vec<String>> {
+    let details = repo.split('/').collect::<Vec<&str>>();
+    let instance = octocrab::instance();
+    let first_page = instance
+        .pulls(details[0], details[1])
+        .list_reviews(number)
+        .per_page(100)
+        .send()
+        .await?;
+    let reviews = instance.all_pages(first_page).await?;
+
+    let mut latest = LatestReviews::new();
+    for review in reviews {
+        let reviewer = match review.user {
+            Some(user) => user.login,
+            None => continue,
+        };
+
+        if let Some(
+            state
+            @ (ReviewState::Approved | ReviewState::ChangesRequested | ReviewState::Dismissed),
+        ) = review.state
+        {
+            latest.insert(reviewer, state);
+        }
+    }
+
+    Ok(unmet_requirements(project, &latest))
+}
+
+/// Each reviewer's latest approving or blocking review.
+type LatestReviews = HashMap<String, ReviewState>;
+
+/// The requirements of the project's merge settings that `latest` doesn't meet yet.
+fn unmet_requirements(project: &Project, latest: &LatestReviews) -> Vec<String> {
+    let approvers = latest
+        .iter()
+        .filter(|(_, state)| **state == ReviewState::Approved)
+        .map(|(reviewer, _)| reviewer.clone())
+        .collect::<Vec<String>>();
+    let mut blockers = latest
+        .iter()
+        .filter(|(_, state)| **state == ReviewState::ChangesRequested)
+        .map(|(reviewer, _)| format!("@{}", reviewer))
+        .collect::<Vec<String>>();
+    blockers.sort();
+
+    let settings = &project.merge;
+    let mut missing = vec![];
+
+    if approvers.len() < settings.required_approvals {
+        let remaining = settings.required_approvals - approvers.len();
+        missing.push(format!(
+            "{} more approval{} ({} of {})",
+            remaining,
+            if remaining == 1 { "" } else { "s" },
+            approvers.len(),
+            settings.required_approvals
+        ));
+    }
+
+    if settings.require_owner_approval
+        && !approvers
+            .iter()
+            .any(|approver| project.project_owners.contains(approver))
+    {
+        missing.push(format!(
+            "an approval from a project owner ({})",
+            project
+                .project_owners
+                .iter()
+                .map(|owner| format!("@{}", owner))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+
+    if !blockers.is_empty() {
+        missing.push(format!(
+            "the changes requested by {} to be resolved",
+            blockers.join(", ")
+        ));
+    }
+
+    missing
+}
+
+/// Merges an approved pull request once the project's approval requirements are
+/// met and its checks pass. While checks are pending the merge is recorded in the
+/// state and retried by `retry_pending_merges` when GitHub reports new results.
+/// `retry` is set for those retries, which stay quiet while still waiting.
+pub async fn try_merge(
+    project: &Project,
+    number: u64,
+    approver: &str,
+    retry: bool,
+) -> error::Result<()> {
+    let repo = match project.github_repo.as_ref() {
+        Some(repo) => repo,
+        None => return Ok(()),
+    };
+    let details = repo.split('/').collect::<Vec<&str>>();
+
+    let instance = octocrab::instance();
+    let issue_handler = instance.issues(details[0], details[1]);
+    let pr_handler = instance.pulls(details[0], details[1]);
+
+    let pull_request = pr_handler.get(number).await?;
+
+    if pull_request.merged_at.is_some() || pull_request.closed_at.is_some() {
+        forget_pending_merge(repo, number);
+        return Ok(());
+    }
+
+    let branch = pull_request.base.ref_field.clone();
+    let head_sha = pull_request.head.sha.clone();
+
+    // Check the project's approval requirements are met
+    let missing = missing_approvals(project, repo, number).await?;
+
+    if !missing.is_empty() {
+        forget_pending_merge(repo, number);
+        show_status(
+            repo,
+            number,
+            format!("👀 Still needs {}", missing.join(", ")),
+        )
+        .await;
+
+        if !retry {
+            comment(
+                &issue_handler,
+                number,
+                format!(
+                    "Thanks @{} for reviewing. Before this can be merged automatically it still needs:\n{}",
+                    approver,
+                    missing
+                        .iter()
+                        .map(|f| format!("- {}", f))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ),
+            )
+            .await;
+        }
+        return Ok(());
+    }
+
+    // Check CI before merging
+    let status = checks_status(repo, &head_sha, &project.merge.required_checks).await?;
+
+    match status {
+        ChecksStatus::Pending(pending) => {
+            update_state(|state| {
+                state.pending_merges.insert(
+                    pull_request_key(repo, number),
+                    PendingMerge {
+                        repo: repo.clone(),
+                        number,
+                        head_sha,
+                        approver: approver.to_string(),
+                    },
+                )
+            });
+            show_status(
+                repo,
+                number,
+                format!("⏳ Approved, waiting on checks: {}", pending.join(", ")),
+            )
+            .await;
+
+            if !retry {
+                comment(
+                    &issue_handler,
+                    number,
+                    format!(
+                        "Thanks @{} for reviewing. This will be merged into {} once these checks pass: {} ⏳",
+                        approver,
+                        branch,
+                        pending.join(", ")
+                    ),
+                )
+                .await;
+            }
+        }
+        ChecksStatus::Failed(failed) => {
+            forget_pending_merge(repo, number);
+
+            comment(
+                &issue_handler,
+                number,
+                format!(
+                    "This PR was approved by @{} but will not be merged automatically because these checks failed: {} 😟",
+                    approver,
+                    failed.join(", ")
+                ),
+            )
+            .await;
+
+            // Notify slack
+            notify(
+                &project.slack_channel,
+                repo,
+                number,
+                Event::Updated,
+                format!(
+                    "A <https://github.com/{repo}/pull/{num}|PR> has been approved on <https://github.com/{repo}|{repo}> but was not merged because checks failed: {checks}.",
+                    repo=repo,
+                    num=number,
+                    checks=failed.join(", ")
+                ),
+                Some(format!("❌ Checks failed: {}", failed.join(", "))),
+            ).await?;
+        }
+        ChecksStatus::Passed => {
+            forget_pending_merge(repo, number);
+
+            let merged = pr_handler
+                .merge(number)
+                .method(project.merge.method)
+                .sha(head_sha)
+                .message(format!(
+                    "🤖 Approved by {} and automatically merged on #{}.",
+                    approver, number
+                ))
+                .send()
+                .await;
+
+            if let Err(e) = merged {
+                println!("Failed to merge {}#{}: {}", repo, number, e);
+
+                comment(
+                    &issue_handler,
+                    number,
+                    format!(
+                        "This PR was approved by @{} but GitHub refused to merge it: {}",
+                        approver, e
+                    ),
+                )
+                .await;
+
+                // Notify slack
+                notify(
+                    &project.slack_channel,
+                    repo,
+                    number,
+                    Event::Updated,
+                    format!(
+                        "A <https://github.com/{repo}/pull/{num}|PR> has been approved on <https://github.com/{repo}|{repo}> but GitHub refused to merge it.",
+                        repo=repo,
+                        num=number,
+                    ),
+                    Some("⚠️ Approved, but GitHub refused to merge it".to_string()),
+                ).await?;
+                return Ok(());
+            }
+
+            comment(
+                &issue_handler,
+                number,
+                format!(
+                    "Thanks @{} for reviewing. This has now been automatically merged into {} 😊",
+                    approver, branch
+                ),
+            )
+            .await;
+
+            // Slack hears about the merge from the pull request's `closed` event
+        }
+    }
+
+    Ok(())
+}
+
+/// Forgets any waiting merge, once a pull request is closed.
+pub fn forget_pull_request(repo: &str, number: u64) {
+    forget_pending_merge(repo, number);
+}
+
+/// Retries the pending merges in `repo` whose checks run on `sha`, called when a
+/// `check_suite` or `status` webhook arrives.
+pub async fn retry_pending_merges(repo: &str, sha: &str) {
+    let pending = read_state()
+        .pending_merges
+        .into_values()
+        .filter(|pending| pending.repo == repo && pending.head_sha == sha)
+        .collect::<Vec<PendingMerge>>();
+
+    if pending.is_empty() {
+        return;
+    }
+
+    let manifest = crate::config::read_manifest();
+    let project = match get_project_by_github_repo(&manifest, repo) {
+        Some(project) => project,
+        None => return,
+    };
+
+    for pending in pending {
+        if let Err(e) = try_merge(project, pending.number, &pending.approver, true).await {
+            error::report(
+                &e,
+                &format!("Merging {}#{}", repo, pending.number),
+                Some(&project.slack_channel),
+            )
+            .await;
+        }
+    }
+}
+
+fn forget_pending_merge(repo: &str, number: u64) {
+    update_state(|state| state.pending_merges.remove(&pull_request_key(repo, number)));
+}
+
+/// Shows the pull request's status in Slack, where a failure is only logged.
+async fn show_status(repo: &str, number: u64, status: String) {
+    if let Err(e) = set_status(repo, number, status).await {
+        println!(
+            "Failed to update the Slack thread of {}#{}: {}",
+            repo, number, e
+        );
+    }
+}
+
+async fn comment(issue_handler: &octocrab::issues::IssueHandler<'_>, number: u64, body: String) {
+    if let Err(e) = issue_handler.create_comment(number, body).await {
+        println!("Failed to create comment: {}", e);
+    }
+}
+
+/// Reads the commit statuses and check runs for `sha`. With no `required` checks,
+/// every reported check must pass, and check suites that haven't created their
+/// check runs yet count as pending; otherwise only the required checks count, which
+/// are pending until they are reported.
+pub async fn checks_status(
+    repo: &str,
+    sha: &str,
+    required: &[String],
+) -> octocrab::Result<ChecksStatus> {
+    let instance = octocrab::instance();
+
+    let statuses = instance
+        .get::<Value, String, ()>(format!("/repos/{}/commits/{}/status", repo, sha), None)
+        .await?;
+    let check_runs = instance
+        .get::<Value, String, _>(
+            format!("/repos/{}/commits/{}/check-runs", repo, sha),
+            Some(&[("per_page", "100")]),
+        )
+        .await?;
+
+    // Each check by name, with `Some(passed)` once it has finished
+    let mut checks = HashMap::new();
+
+    for status in statuses["statuses"].as_array().into_iter().flatten() {
+        let result = match status["state"].as_str() {
+            Some("success") => Some(true),
+            Some("failure") | Some("error") => Some(false),
+            _ => None,
+        };
+        checks.insert(status["context"].as_str().unwrap_or("").to_string(), result);
+    }
+
+    for check_run in check_runs["check_runs"].as_array().into_iter().flatten() {
+        let result = match check_run["status"].as_str() {
+            Some("completed") => Some(matches!(
+                check_run["conclusion"].as_str(),
+                Some("success") | Some("neutral") | Some("skipped")
+            )),
+            _ => None,
+        };
+        checks.insert(check_run["name"].as_str().unwrap_or("").to_string(), result);
+    }
+
+    let names = match required.is_empty() {
+        true => checks.keys().cloned().collect::<Vec<String>>(),
+        false => required.to_vec(),
+    };
+
+    let mut failed = vec![];
+    let mut pending = vec![];
+
+    for name in names {
+        match checks.get(&name) {
+            Some(Some(true)) => (),
+            Some(Some(false)) => failed.push(name),
+            _ => pending.push(name),
+        }
+    }
+
+    failed.sort();
+    pending.sort();
+
+    if !failed.is_empty() {
+        Ok(ChecksStatus::Failed(failed))
+    } else if !pending.is_empty() {
+        Ok(ChecksStatus::Pending(pending))
+    } else {
+        Ok(ChecksStatus::Passed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MergeSettings;
+
+    fn project(required_approvals: usize, require_owner_approval: bool) -> Project {
+        Project {
+            project_owners: vec!["owner".to_string()],
+            merge: MergeSettings {
+                required_approvals,
+                require_owner_approval,
+                ..MergeSettings::default()
+            },
+            ..Project::default()
+        }
+    }
+
+    fn reviews(reviews: &[(&str, ReviewState)]) -> LatestReviews {
+        reviews
+            .iter()
+            .map(|(reviewer, state)| (reviewer.to_string(), *state))
+            .collect()
+    }
+
+    #[test]
+    fn counts_distinct_approvals() {
+        let latest = reviews(&[("a", ReviewState::Approved), ("b", ReviewState::Dismissed)]);
+
+        assert_eq!(
+            unmet_requirements(&project(2, false), &latest),
+            vec!["1 more approval (1 of 2)"]
+        );
+        assert!(unmet_requirements(&project(1, false), &latest).is_empty());
+    }
+
+    #[test]
+    fn requires_an_owner_when_configured() {
+        let latest = reviews(&[("a", ReviewState::Approved)]);
+        assert_eq!(
+            unmet_requirements(&project(1, true), &latest),
+            vec!["an approval from a project owner (@owner)"]
+        );
+
+        let latest = reviews(&[("owner", ReviewState::Approved)]);
+        assert!(unmet_requirements(&project(1, true), &latest).is_empty());
+    }
+
+    #[test]
+    fn requested_changes_block_merging() {
+        let latest = reviews(&[
+            ("a", ReviewState::Approved),
+            ("c", ReviewState::ChangesRequested),
+            ("b", ReviewState::ChangesRequested),
+        ]);
+
+        assert_eq!(
+            unmet_requirements(&project(1, false), &latest),
+            vec!["the changes requested by @b, @c to be resolved"]
+        );
+    }
+}
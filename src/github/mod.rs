@@ -1,6 +1,10 @@
 use ::rocket::serde::json::Value;
 
+pub mod codeowners;
+pub mod config;
+pub mod merge;
 pub mod prs;
+pub mod reviewers;
 pub mod rocket;
 
 pub fn setup_octocrab() {
@@ -20,9 +24,42 @@ pub fn github_handler(input: Value, event: crate::github::rocket::GitHubEvent) {
 }
 
 pub async fn handle_github_event(input: Value, event: crate::github::rocket::GitHubEvent) {
-    match event.0.as_str() {
-        "pull_request" => prs::handle_pull_request(input).await,
-        "pull_request_review" => prs::handle_pull_request_review(input).await,
-        _ => (),
+    let handled = match event.0.as_str() {
+        "push" => config::handle_push(&input).await,
+        "pull_request" => prs::handle_pull_request(&input).await,
+        "pull_request_review" => prs::handle_pull_request_review(&input).await,
+        "check_suite" if input["action"] == "completed" => {
+            merge::retry_pending_merges(
+                input["repository"]["full_name"].as_str().unwrap_or(""),
+                input["check_suite"]["head_sha"].as_str().unwrap_or(""),
+            )
+            .await;
+            Ok(())
+        }
+        "status" if input["state"] != "pending" => {
+            merge::retry_pending_merges(
+                input["repository"]["full_name"].as_str().unwrap_or(""),
+                input["sha"].as_str().unwrap_or(""),
+            )
+            .await;
+            Ok(())
+        }
+        _ => Ok(()),
+    };
+
+    if let Err(e) = handled {
+        // Tell the project's channel when the event belongs to a project
+        let manifest = crate::config::read_manifest();
+        let channel = input["repository"]["full_name"]
+            .as_str()
+            .and_then(|repo| crate::config::get_project_by_github_repo(&manifest, repo))
+            .map(|project| project.slack_channel.as_str());
+
+        crate::error::report(
+            &e,
+            &format!("Handling the GitHub {} event", event.0),
+            channel,
+        )
+        .await;
     }
-}
\ No newline at end of file
+}
@@ -2,11 +2,18 @@ use itertools::Itertools;
 
 use crate::{
     config::{get_project_by_github_repo, get_slack_by_github_username},
-    slack::handler::respond_http_text,
+    error::{Error, Payload, Result},
+    github::{
+        codeowners::{find_code_owners, team_members, CodeOwners},
+        merge::{forget_pull_request, try_merge},
+        reviewers::select_reviewers,
+    },
+    jira::issues::{sync_pull_request, PullRequestStage},
+    slack::pull_request::{notify, Event},
 };
 
-pub async fn handle_pull_request(input: ::rocket::serde::json::Value) {
-    let action = input["action"].as_str().unwrap();
+pub async fn handle_pull_request(input: &::rocket::serde::json::Value) -> Result<()> {
+    let action = input.str_at("/action")?;
     let pull_request = input["pull_request"].clone();
 
     println!("Received GitHub pull request event: {:?}", action);
@@ -15,267 +22,397 @@ pub async fn handle_pull_request(input: ::rocket::serde::json::Value) {
         "reopened" | "opened" | "ready_for_review" => {
             // Find project by GitHub repo and assign users
 
+            let number = pull_request.u64_at("/number")?;
+            let author = pull_request.str_at("/user/login")?;
+            let base = pull_request.str_at("/base/ref")?;
+            let head_repo = pull_request.str_at("/head/repo/full_name")?;
+
             let manifest = crate::config::read_manifest();
-            let project = get_project_by_github_repo(
-                &manifest,
-                pull_request["head"]["repo"]["full_name"].as_str().unwrap(),
-            );
-
-            if project.is_none() {
-                println!(
-                    "No project found for GitHub repo: {}",
-                    pull_request["head"]["repo"]["full_name"].as_str().unwrap()
-                );
-                return;
-            }
+            let project = match get_project_by_github_repo(&manifest, head_repo) {
+                Some(project) => project,
+                None => {
+                    println!("No project found for GitHub repo: {}", head_repo);
+                    return Ok(());
+                }
+            };
 
-            let project = project.unwrap();
-            let repo = project.github_repo.as_ref().unwrap();
+            let repo = project
+                .github_repo
+                .as_ref()
+                .ok_or(Error::Payload(format!("{} has no GitHub repo", head_repo)))?;
             let details = repo.split("/").collect::<Vec<&str>>();
 
             let instance = octocrab::instance();
-            let issue_handler = instance.issues(details[0].clone(), details[1].clone());
-            let pr_handler = instance.pulls(details[0].clone(), details[1].clone());
+            let issue_handler = instance.issues(details[0], details[1]);
+            let pr_handler = instance.pulls(details[0], details[1]);
 
             let contributors = instance
                 .get::<Vec<octocrab::models::Author>, String, ()>(
-                    format!(
-                        "/repos/{}/{}/contributors",
-                        details[0].clone(),
-                        details[1].clone()
-                    ),
+                    format!("/repos/{}/{}/contributors", details[0], details[1]),
                     None::<&()>,
                 )
-                .await
-                .expect("Failed to get contributors");
+                .await?;
 
             // TODO: Clean up this filter
             let reviewers = project
                 .project_owners
-                .clone()
                 .iter()
                 .chain(manifest.managers.iter())
-                .filter(|f| **f != pull_request["user"]["login"].as_str().unwrap())
+                .filter(|f| **f != author)
                 .filter(|f| contributors.iter().any(|c| c.login == **f))
                 .map(|f| f.to_owned())
                 .unique()
                 .collect::<Vec<String>>();
 
-            issue_handler
-                .add_assignees(
-                    pull_request["number"].as_u64().unwrap(),
-                    &[pull_request["user"]["login"].as_str().unwrap()],
-                )
-                .await
-                .expect("Failed to assign user");
+            issue_handler.add_assignees(number, &[author]).await?;
 
-            // Do not request if merging into the wrong branch
-            if vec!["master", "main"].contains(&pull_request["base"]["ref"].as_str().unwrap()) {
+            sync_pull_request(project, &pull_request, PullRequestStage::Opened).await;
+
+            // Only request reviews if the branch policy asks for them
+            let rule = project.branch_rule(base);
+            if !rule.assign_reviewers {
                 issue_handler
                     .create_comment(
-                        pull_request["number"].as_u64().unwrap(),
+                        number,
                         format!(
-                            "Thanks @{}. This PR is being merged into {}, so I will not request reviews in case this is an error.",
-                            pull_request["user"]["login"].as_str().unwrap(),
-                            pull_request["base"]["ref"].as_str().unwrap()
+                            "Thanks @{}. Reviews are not requested automatically for PRs into {}.",
+                            author, base
                         ),
                     )
-                    .await
-                    .expect("Failed to create comment");
-                return;
+                    .await?;
+
+                // Notify slack
+                notify(
+                    &project.slack_channel,
+                    repo,
+                    number,
+                    Event::Opened { reviewers: &[] },
+                    format!(
+                        "A new <https://github.com/{repo}/pull/{num}|PR> has been opened into {branch} on <https://github.com/{repo}|{repo}>.",
+                        repo=repo,
+                        num=number,
+                        branch=base
+                    ),
+                    Some(format!("🆕 Open, reviews aren't requested automatically for {}", base)),
+                ).await?;
+                return Ok(());
             }
 
+            // Picked only now, as round-robin moves on to the next reviewer
+            let reviewers = select_reviewers(project, repo, reviewers).await;
+
+            // Also request the code owners of the changed files
+            let code_owners = find_code_owners(repo, number, base)
+                .await
+                .unwrap_or_else(|e| {
+                    println!("Failed to find code owners: {}", e);
+                    CodeOwners::default()
+                });
+
+            let reviewers = reviewers
+                .into_iter()
+                .chain(code_owners.users)
+                .filter(|f| f != author)
+                .unique()
+                .collect::<Vec<String>>();
+            let teams = code_owners.teams;
+
             let reviewed = pr_handler
                 .request_reviews(
-                    pull_request["number"].as_u64().unwrap(),
+                    number,
                     reviewers.clone(),
-                    vec![],
+                    teams
+                        .iter()
+                        .filter_map(|f| f.rsplit('/').next())
+                        .map(String::from)
+                        .collect::<Vec<String>>(),
                 )
                 .await;
 
             let formatted_reviewers = reviewers
-                .clone()
-                .into_iter()
+                .iter()
+                .chain(teams.iter())
                 .map(|f| format!("@{}", f))
                 .collect::<Vec<String>>()
                 .join(", ");
-            let slack_reviewers = reviewers
-                .clone()
-                .into_iter()
-                .map(|f| match get_slack_by_github_username(&manifest, &f) {
+            let mut slack_reviewers = reviewers
+                .iter()
+                .map(|f| match get_slack_by_github_username(&manifest, f) {
                     Some(slack) => format!("<@{}>", slack),
                     None => format!("@{}", f),
                 })
-                .collect::<Vec<String>>()
-                .join(" ");
-
-            if reviewed.is_ok() {
-                issue_handler
-                    .create_comment(
-                        pull_request["number"].as_u64().unwrap(),
-                        format!(
-                            "Thanks @{}. Reviews have been requested from the following project managers: {} 😊",
-                            pull_request["user"]["login"].as_str().unwrap(),
-                            formatted_reviewers
-                        ),
-                    )
-                    .await
-                    .expect("Failed to create comment");
+                .collect::<Vec<String>>();
 
-                // Notify slack
-                let _ = respond_http_text(
-                    &project.slack_channel,
-                    format!(
-                        "A new <https://github.com/{repo}/pull/{num}|PR> has been requested on <https://github.com/{repo}|{repo}> and reviews have been requested from {reviewers}. 😊",
-                        repo=repo,
-                        num=pull_request["number"].as_u64().unwrap(),
-                        reviewers=slack_reviewers
-                    )
-                ).await;
-            } else {
-                println!("{}", reviewed.err().unwrap());
-                issue_handler
-                    .create_comment(
-                        pull_request["number"].as_u64().unwrap(),
-                        format!(
-                            "Thanks @{}. I was unable to automatically assign reviews for this PR. Please add them manually: {}. 😇",
-                            pull_request["user"]["login"].as_str().unwrap(),
-                            reviewers.clone().into_iter().map(|f| format!("@{}", f)).collect::<Vec<String>>().join(", ")
-                        ),
-                    )
+            // Mention the linked members of requested teams, or the team if none are linked
+            for team in &teams {
+                let members = team_members(team)
                     .await
-                    .expect("Failed to create comment");
-
-                // Notify slack
-                let _ = respond_http_text(
-                    &project.slack_channel,
-                    format!(
-                        "A new <https://github.com/{repo}/pull/{num}|PR> has been requested on <https://github.com/{repo}|{repo}> but I was unable to automatically assign reviewers.",
-                        repo=repo,
-                        num=pull_request["number"].as_u64().unwrap(),
-                    )
-                ).await;
-            }
-        }
-        _ => (),
-    }
-}
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|f| f != author)
+                    .filter(|f| !reviewers.contains(f))
+                    .filter_map(|f| get_slack_by_github_username(&manifest, &f))
+                    .map(|slack| format!("<@{}>", slack))
+                    .collect::<Vec<String>>();
 
-pub async fn handle_pull_request_review(input: ::rocket::serde::json::Value) {
-    let action = input["action"].as_str().unwrap();
-    let pull_request = input["pull_request"].clone();
-    let review = input["review"].clone();
-
-    println!("Received GitHub pull request review event: {:?}", action);
-
-    match action {
-        "submitted" => {
-            // Find project by GitHub repo and assign users
-
-            let manifest = crate::config::read_manifest();
-            let project = get_project_by_github_repo(
-                &manifest,
-                pull_request["head"]["repo"]["full_name"].as_str().unwrap(),
-            );
-
-            if project.is_none() {
-                println!(
-                    "No project found for GitHub repo: {}",
-                    pull_request["head"]["repo"]["full_name"].as_str().unwrap()
-                );
-                return;
+                match members.is_empty() {
+                    true => slack_reviewers.push(format!("@{}", team)),
+                    false => slack_reviewers.extend(members),
+                }
             }
 
-            let project = project.unwrap();
-            let repo = project.github_repo.as_ref().unwrap();
-            let details = repo.split("/").collect::<Vec<&str>>();
-
-            let instance = octocrab::instance();
-            let issue_handler = instance.issues(details[0].clone(), details[1].clone());
-            let pr_handler = instance.pulls(details[0].clone(), details[1].clone());
-
-            println!("Review state: {}", review["state"].as_str().unwrap());
-
-            match review["state"].as_str().unwrap() {
-                "approved" => {
-                    // Do not merge if merging into the wrong branch
-                    if vec!["master", "main"]
-                        .contains(&pull_request["base"]["ref"].as_str().unwrap())
-                    {
-                        issue_handler
-                            .create_comment(
-                                pull_request["number"].as_u64().unwrap(),
-                                format!(
-                                    "Thanks @{} for reviewing. This PR is being merged into {}, so I will not merge automatically in case this is an error.",
-                                    review["user"]["login"].as_str().unwrap(),
-                                    pull_request["base"]["ref"].as_str().unwrap()
-                                ),
-                            )
-                            .await
-                            .expect("Failed to create comment");
-                        return;
-                    }
-
-                    pr_handler
-                        .merge(pull_request["number"].as_u64().unwrap())
-                        .message(format!(
-                            "🤖 Approved by {} and automatically merged on #{}.",
-                            review["user"]["login"].as_str().unwrap(),
-                            pull_request["number"].as_u64().unwrap()
-                        ))
-                        .send()
-                        .await
-                        .expect("Failed to merge PR");
+            let slack_reviewers = slack_reviewers.into_iter().unique().join(" ");
 
+            match reviewed {
+                Ok(_) => {
                     issue_handler
                         .create_comment(
-                            pull_request["number"].as_u64().unwrap(),
+                            number,
                             format!(
-                                "Thanks @{} for reviewing. This will now be automatically merged into {} 😊",
-                                review["user"]["login"].as_str().unwrap(),
-                                pull_request["base"]["ref"].as_str().unwrap()
+                                "Thanks @{}. Reviews have been requested from the following project managers: {} 😊",
+                                author,
+                                formatted_reviewers
                             ),
                         )
-                        .await
-                        .expect("Failed to create comment");
+                        .await?;
 
                     // Notify slack
-                    let _ = respond_http_text(
+                    notify(
                         &project.slack_channel,
+                        repo,
+                        number,
+                        Event::Opened { reviewers: &reviewers },
                         format!(
-                            "A <https://github.com/{repo}/pull/{num}|PR> has been approved on <https://github.com/{repo}|{repo}> and will be automatically merged into {branch}. 😊",
+                            "A new <https://github.com/{repo}/pull/{num}|PR> has been requested on <https://github.com/{repo}|{repo}> and reviews have been requested from {reviewers}. 😊",
                             repo=repo,
-                            num=pull_request["number"].as_u64().unwrap(),
-                            branch=pull_request["base"]["ref"].as_str().unwrap()
-                        )
-                    ).await;
+                            num=number,
+                            reviewers=slack_reviewers
+                        ),
+                        Some(format!("👀 Waiting on review from {}", slack_reviewers)),
+                    ).await?;
                 }
-                "changes_requested" => {
+                Err(e) => {
+                    println!("Failed to request reviews: {}", Error::from(e));
                     issue_handler
                         .create_comment(
-                            pull_request["number"].as_u64().unwrap(),
+                            number,
                             format!(
-                                "Thanks @{} for reviewing. @{}, make sure these changes are made and a review is requested 😄",
-                                review["user"]["login"].as_str().unwrap(),
-                                pull_request["user"]["login"].as_str().unwrap()
+                                "Thanks @{}. I was unable to automatically assign reviews for this PR. Please add them manually: {}. 😇",
+                                author,
+                                formatted_reviewers
                             ),
                         )
-                        .await
-                        .expect("Failed to create comment");
+                        .await?;
 
                     // Notify slack
-                    let _ = respond_http_text(
+                    notify(
                         &project.slack_channel,
+                        repo,
+                        number,
+                        Event::Opened { reviewers: &[] },
                         format!(
-                            "A <https://github.com/{repo}/pull/{num}|PR> has had changes requested on <https://github.com/{repo}|{repo}> and will need to be updated. 😊",
+                            "A new <https://github.com/{repo}/pull/{num}|PR> has been requested on <https://github.com/{repo}|{repo}> but I was unable to automatically assign reviewers.",
                             repo=repo,
-                            num=pull_request["number"].as_u64().unwrap(),
-                        )
-                    ).await;
+                            num=number,
+                        ),
+                        Some("⚠️ Reviewers need to be added by hand".to_string()),
+                    ).await?;
                 }
-                _ => (),
             }
         }
+        "review_requested" => {
+            // Teams are requested by name, without a login
+            let reviewer = match input["requested_reviewer"]["login"].as_str() {
+                Some(reviewer) => reviewer,
+                None => return Ok(()),
+            };
+
+            let number = pull_request.u64_at("/number")?;
+            let repo = pull_request.str_at("/base/repo/full_name")?;
+
+            let manifest = crate::config::read_manifest();
+            let project = match get_project_by_github_repo(&manifest, repo) {
+                Some(project) => project,
+                None => return Ok(()),
+            };
+
+            let mention = |login: &str| match get_slack_by_github_username(&manifest, login) {
+                Some(slack) => format!("<@{}>", slack),
+                None => format!("@{}", login),
+            };
+            let waiting_on = pull_request["requested_reviewers"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|f| f["login"].as_str())
+                .map(mention)
+                .join(" ");
+
+            notify(
+                &project.slack_channel,
+                repo,
+                number,
+                Event::ReviewRequested { reviewer },
+                format!("👀 A review has been requested from {}.", mention(reviewer)),
+                Some(format!("👀 Waiting on review from {}", waiting_on)),
+            )
+            .await?;
+        }
+        "closed" => {
+            let merged = pull_request["merged"].as_bool().unwrap_or(false);
+            let number = pull_request.u64_at("/number")?;
+            let base = pull_request.str_at("/base/ref")?;
+            let repo = pull_request.str_at("/base/repo/full_name")?;
+
+            forget_pull_request(repo, number);
+
+            let manifest = crate::config::read_manifest();
+            let project = match get_project_by_github_repo(&manifest, repo) {
+                Some(project) => project,
+                None => return Ok(()),
+            };
+
+            if !merged {
+                notify(
+                    &project.slack_channel,
+                    repo,
+                    number,
+                    Event::Closed,
+                    "🚫 This PR has been closed without being merged.".to_string(),
+                    Some("🚫 Closed".to_string()),
+                )
+                .await?;
+                return Ok(());
+            }
+
+            sync_pull_request(project, &pull_request, PullRequestStage::Merged).await;
+
+            notify(
+                &project.slack_channel,
+                repo,
+                number,
+                Event::Merged,
+                format!(
+                    "🎉 <https://github.com/{repo}/pull/{num}|PR #{num}> on <https://github.com/{repo}|{repo}> has been merged into {branch} by @{merged_by}.",
+                    repo = repo,
+                    num = number,
+                    branch = base,
+                    merged_by = pull_request["merged_by"]["login"].as_str().unwrap_or("someone")
+                ),
+                Some(format!("🎉 Merged into {}", base)),
+            )
+            .await?;
+        }
         _ => (),
     }
+
+    Ok(())
+}
+
+pub async fn handle_pull_request_review(input: &::rocket::serde::json::Value) -> Result<()> {
+    let action = input.str_at("/action")?;
+    let pull_request = input["pull_request"].clone();
+    let review = input["review"].clone();
+
+    println!("Received GitHub pull request review event: {:?}", action);
+
+    if action != "submitted" {
+        return Ok(());
+    }
+
+    // Find project by GitHub repo and assign users
+
+    let number = pull_request.u64_at("/number")?;
+    let author = pull_request.str_at("/user/login")?;
+    let base = pull_request.str_at("/base/ref")?;
+    let head_repo = pull_request.str_at("/head/repo/full_name")?;
+    let reviewer = review.str_at("/user/login")?;
+    let state = review.str_at("/state")?;
+
+    let manifest = crate::config::read_manifest();
+    let project = match get_project_by_github_repo(&manifest, head_repo) {
+        Some(project) => project,
+        None => {
+            println!("No project found for GitHub repo: {}", head_repo);
+            return Ok(());
+        }
+    };
+
+    let repo = project
+        .github_repo
+        .as_ref()
+        .ok_or(Error::Payload(format!("{} has no GitHub repo", head_repo)))?;
+    let details = repo.split("/").collect::<Vec<&str>>();
+
+    let instance = octocrab::instance();
+    let issue_handler = instance.issues(details[0], details[1]);
+
+    println!("Review state: {}", state);
+
+    match state {
+        "approved" => {
+            sync_pull_request(project, &pull_request, PullRequestStage::Approved).await;
+
+            // Only merge if the branch policy allows it
+            let rule = project.branch_rule(base);
+            if !rule.auto_merge {
+                issue_handler
+                    .create_comment(
+                        number,
+                        format!(
+                            "Thanks @{} for reviewing. PRs into {} are not merged automatically.",
+                            reviewer, base
+                        ),
+                    )
+                    .await?;
+
+                // Notify slack
+                notify(
+                    &project.slack_channel,
+                    repo,
+                    number,
+                    Event::Updated,
+                    format!(
+                        "A <https://github.com/{repo}/pull/{num}|PR> has been approved on <https://github.com/{repo}|{repo}> and is ready to be merged into {branch}.",
+                        repo=repo,
+                        num=number,
+                        branch=base
+                    ),
+                    Some(format!("✅ Approved by @{}, ready to be merged into {}", reviewer, base)),
+                ).await?;
+                return Ok(());
+            }
+
+            try_merge(project, number, reviewer, false).await?;
+        }
+        "changes_requested" => {
+            issue_handler
+                .create_comment(
+                    number,
+                    format!(
+                        "Thanks @{} for reviewing. @{}, make sure these changes are made and a review is requested 😄",
+                        reviewer,
+                        author
+                    ),
+                )
+                .await?;
+
+            // Notify slack
+            notify(
+                &project.slack_channel,
+                repo,
+                number,
+                Event::Updated,
+                format!(
+                    "A <https://github.com/{repo}/pull/{num}|PR> has had changes requested on <https://github.com/{repo}|{repo}> and will need to be updated. 😊",
+                    repo=repo,
+                    num=number,
+                ),
+                Some(format!("✏️ Changes requested by @{}", reviewer)),
+            ).await?;
+        }
+        _ => (),
+    }
+
+    Ok(())
 }
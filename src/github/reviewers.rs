@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use octocrab::params;
+use rand::seq::SliceRandom;
+
+use crate::config::{state::update_state, Project, ReviewerStrategy};
+
+/// Picks who to request reviews from out of `candidates` using the project's
+/// reviewer strategy.
+pub async fn select_reviewers(
+    project: &Project,
+    repo: &str,
+    candidates: Vec<String>,
+) -> Vec<String> {
+    match project.reviewers {
+        ReviewerStrategy::All => candidates,
+        ReviewerStrategy::Random { count } => random(candidates, count),
+        ReviewerStrategy::RoundRobin { count } => round_robin(repo, candidates, count),
+        ReviewerStrategy::LeastLoaded { count } => least_loaded(repo, candidates, count).await,
+    }
+}
+
+fn random(mut candidates: Vec<String>, count: usize) -> Vec<String> {
+    candidates.shuffle(&mut rand::thread_rng());
+    candidates.truncate(count);
+    candidates
+}
+
+/// Takes the next `count` candidates in alphabetical order after the reviewer picked
+/// last time, wrapping around. Remembering a name rather than a position keeps the
+/// rotation fair when people are added or removed.
+fn round_robin(repo: &str, mut candidates: Vec<String>, count: usize) -> Vec<String> {
+    if candidates.is_empty() {
+        return candidates;
+    }
+
+    candidates.sort();
+
+    update_state(|state| {
+        let start = state
+            .last_reviewers
+            .get(repo)
+            .and_then(|last| candidates.iter().position(|candidate| candidate > last))
+            .unwrap_or(0);
+
+        let selected = candidates
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(count.min(candidates.len()))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        if let Some(last) = selected.last() {
+            state.last_reviewers.insert(repo.to_string(), last.clone());
+        }
+
+        selected
+    })
+}
+
+/// Takes the `count` candidates with the fewest pending review requests across the
+/// repository's open pull requests, breaking ties at random.
+async fn least_loaded(repo: &str, candidates: Vec<String>, count: usize) -> Vec<String> {
+    let loads = match review_loads(repo).await {
+        Ok(loads) => loads,
+        Err(e) => {
+            println!("Failed to count review requests for {}: {}", repo, e);
+            return random(candidates, count);
+        }
+    };
+
+    let mut candidates = random(candidates, usize::MAX);
+    candidates.sort_by_key(|candidate| loads.get(candidate).copied().unwrap_or(0));
+    candidates.truncate(count);
+    candidates
+}
+
+/// How many open pull requests in `repo` are waiting on each reviewer.
+pub async fn review_loads(repo: &str) -> octocrab::Result<HashMap<String, usize>> {
+    let details = repo.split('/').collect::<Vec<&str>>();
+    let pull_requests = octocrab::instance()
+        .pulls(details[0], details[1])
+        .list()
+        .state(params::State::Open)
+        .per_page(100)
+        .send()
+        .await?;
+
+    let mut loads = HashMap::new();
+    for pull_request in pull_requests.items {
+        for reviewer in pull_request.requested_reviewers.unwrap_or_default() {
+            *loads.entry(reviewer.login).or_insert(0) += 1;
+        }
+    }
+
+    Ok(loads)
+}
@@ -1,7 +1,15 @@
+use std::env;
 
-use std::convert::Infallible;
+use rocket::{
+    data::{self, Data, FromData, Limits},
+    http::Status,
+    outcome::Outcome,
+    request::{self, FromRequest},
+    serde::json::{serde_json, Value},
+    Request,
+};
 
-use rocket::{request::{FromRequest, self, Outcome}, Request, http::Status};
+use crate::config::{get_project_name_by_github_repo, Manifest};
 
 #[derive(Debug)]
 pub struct GitHubEvent(pub String);
@@ -14,11 +22,233 @@ impl<'r> FromRequest<'r> for GitHubEvent {
         let event = request.headers().get_one("X-GitHub-Event");
         match event {
           Some(event) => {
-            // check validity
             Outcome::Success(GitHubEvent(event.to_string()))
           },
           // token does not exist
           None => Outcome::Failure((Status::Unauthorized, ()))
         }
     }
-}
\ No newline at end of file
+}
+
+/// The JSON body of a webhook delivery, only produced once `X-Hub-Signature-256`
+/// matches the raw body. The body isn't parsed until then.
+#[derive(Debug)]
+pub struct SignedPayload(pub Value);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SignedPayload {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let delivery = request
+            .headers()
+            .get_one("X-GitHub-Delivery")
+            .unwrap_or("unknown");
+
+        let limit = request.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => {
+                println!("Rejected GitHub delivery {}: body is too large", delivery);
+                return Outcome::Failure((Status::PayloadTooLarge, ()));
+            }
+            Err(e) => {
+                println!("Rejected GitHub delivery {}: {}", delivery, e);
+                return Outcome::Failure((Status::BadRequest, ()));
+            }
+        };
+
+        let signature = match request
+            .headers()
+            .get_one("X-Hub-Signature-256")
+            .and_then(|signature| signature.strip_prefix("sha256="))
+        {
+            Some(signature) => signature,
+            None => {
+                println!(
+                    "Rejected GitHub delivery {}: missing X-Hub-Signature-256",
+                    delivery
+                );
+                return Outcome::Failure((Status::Unauthorized, ()));
+            }
+        };
+
+        let manifest = crate::config::read_manifest();
+        let secrets = webhook_secrets(&manifest);
+        if secrets.is_empty() {
+            println!(
+                "Rejected GitHub delivery {}: no webhook secret configured",
+                delivery
+            );
+            return Outcome::Failure((Status::Unauthorized, ()));
+        }
+
+        let signer = match signer(&secrets, &body, signature) {
+            Some(signer) => signer,
+            None => {
+                println!("Rejected GitHub delivery {}: signature mismatch", delivery);
+                return Outcome::Failure((Status::Unauthorized, ()));
+            }
+        };
+
+        let input: Value = match serde_json::from_slice(&body) {
+            Ok(input) => input,
+            Err(e) => {
+                println!("Rejected GitHub delivery {}: {}", delivery, e);
+                return Outcome::Failure((Status::BadRequest, ()));
+            }
+        };
+
+        // The repository in the body can only be trusted now, so check it belongs to
+        // the project whose secret signed it.
+        let repo = input["repository"]["full_name"].as_str().unwrap_or("");
+        if !signed_for(&manifest, &secrets, signer.as_deref(), repo) {
+            println!(
+                "Rejected GitHub delivery {} for {}: signed with another project's secret",
+                delivery, repo
+            );
+            return Outcome::Failure((Status::Unauthorized, ()));
+        }
+
+        Outcome::Success(SignedPayload(input))
+    }
+}
+
+/// A webhook secret, along with the project it belongs to, or `None` for the
+/// deployment wide `GITHUB_WEBHOOK_SECRET`.
+type Secret = (Option<String>, String);
+
+/// The secrets a delivery may be signed with. A project can override the deployment
+/// wide secret with `GITHUB_WEBHOOK_SECRET_<PROJECT>`, e.g.
+/// `GITHUB_WEBHOOK_SECRET_MY_PROJECT` for the project `my-project`.
+fn webhook_secrets(manifest: &Manifest) -> Vec<Secret> {
+    manifest
+        .projects
+        .keys()
+        .filter_map(|project_name| {
+            project_secret(project_name).map(|secret| (Some(project_name.clone()), secret))
+        })
+        .chain(
+            env::var("GITHUB_WEBHOOK_SECRET")
+                .ok()
+                .map(|secret| (None, secret)),
+        )
+        .filter(|(_, secret)| !secret.is_empty())
+        .collect()
+}
+
+fn project_secret(project_name: &str) -> Option<String> {
+    let suffix = project_name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect::<String>();
+
+    env::var(format!("GITHUB_WEBHOOK_SECRET_{}", suffix))
+        .ok()
+        .filter(|secret| !secret.is_empty())
+}
+
+/// The project whose secret `signature` was made with over `body`, or `None` inside
+/// for the deployment wide secret.
+fn signer(secrets: &[Secret], body: &[u8], signature: &str) -> Option<Option<String>> {
+    secrets
+        .iter()
+        .find(|(_, secret)| crate::signature::verify(secret.as_bytes(), body, signature))
+        .map(|(signer, _)| signer.clone())
+}
+
+/// Whether a delivery for `repo` may be signed by `signer`: a project's own secret
+/// only covers its repository, and the deployment wide one covers every repository
+/// whose project doesn't have its own.
+fn signed_for(manifest: &Manifest, secrets: &[Secret], signer: Option<&str>, repo: &str) -> bool {
+    let project_name = get_project_name_by_github_repo(manifest, repo);
+
+    match signer {
+        Some(signer) => project_name == Some(signer),
+        None => project_name.is_none_or(|project_name| {
+            !secrets
+                .iter()
+                .any(|(owner, _)| owner.as_deref() == Some(project_name))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::*;
+    use crate::config::schema;
+
+    fn manifest() -> Manifest {
+        schema::parse(
+            r#"
+            version = 1
+            managers = []
+            configured_project = "ctrl"
+
+            [projects.signed-alone]
+            slack_channel = "C1"
+            github_repo = "octo/alone"
+            project_owners = []
+
+            [projects.signed-shared]
+            slack_channel = "C2"
+            github_repo = "octo/shared"
+            project_owners = []
+
+            [profiles]
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn secrets() -> Vec<Secret> {
+        vec![
+            (Some("signed-alone".to_string()), "alone".to_string()),
+            (None, "global".to_string()),
+        ]
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn finds_the_secret_a_delivery_was_signed_with() {
+        let body = br#"{"zen":"Keep it logically awesome."}"#;
+
+        assert_eq!(
+            signer(&secrets(), body, &sign("alone", body)),
+            Some(Some("signed-alone".to_string()))
+        );
+        assert_eq!(signer(&secrets(), body, &sign("global", body)), Some(None));
+        assert_eq!(signer(&secrets(), body, &sign("guessed", body)), None);
+    }
+
+    #[test]
+    fn project_secrets_only_cover_their_repository() {
+        let (manifest, secrets) = (manifest(), secrets());
+        let signed = |repo| signed_for(&manifest, &secrets, Some("signed-alone"), repo);
+
+        assert!(signed("octo/alone"));
+        assert!(!signed("octo/shared"));
+        assert!(!signed("octo/unknown"));
+    }
+
+    #[test]
+    fn global_secret_covers_projects_without_their_own() {
+        let (manifest, secrets) = (manifest(), secrets());
+        let signed = |repo| signed_for(&manifest, &secrets, None, repo);
+
+        assert!(signed("octo/shared"));
+        assert!(signed("octo/unknown"));
+        assert!(!signed("octo/alone"));
+    }
+}
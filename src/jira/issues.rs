@@ -0,0 +1,97 @@
+use rocket::serde::json::Value;
+
+use super::{find_issue_keys, JiraClient};
+use crate::config::Project;
+
+/// How far a pull request has got, used to pick the status its issues move to.
+#[derive(Debug, Clone, Copy)]
+pub enum PullRequestStage {
+    Opened,
+    Approved,
+    Merged,
+}
+
+/// Moves the Jira issues mentioned in a pull request's title or branch to the
+/// project's configured status for `stage`. When the pull request is opened the
+/// issue links are also commented on it.
+pub async fn sync_pull_request(project: &Project, pull_request: &Value, stage: PullRequestStage) {
+    let jira_project = match project.jira_project {
+        Some(ref jira_project) => jira_project,
+        None => return,
+    };
+
+    let client = match JiraClient::from_env() {
+        Some(client) => client,
+        None => {
+            println!("Jira is not configured, skipping issue sync");
+            return;
+        }
+    };
+
+    let keys = find_issue_keys(
+        jira_project,
+        &format!(
+            "{} {}",
+            pull_request["title"].as_str().unwrap_or(""),
+            pull_request["head"]["ref"].as_str().unwrap_or("")
+        ),
+    );
+
+    if keys.is_empty() {
+        return;
+    }
+
+    if let PullRequestStage::Opened = stage {
+        comment_issue_links(&client, project, pull_request, &keys).await;
+    }
+
+    let status = match stage {
+        PullRequestStage::Opened => &project.jira_statuses.opened,
+        PullRequestStage::Approved => &project.jira_statuses.approved,
+        PullRequestStage::Merged => &project.jira_statuses.merged,
+    };
+
+    let status = match status {
+        Some(status) => status,
+        None => return,
+    };
+
+    for key in keys {
+        match client.transition_issue(&key, status).await {
+            Ok(true) => println!("Moved {} to {}", key, status),
+            Ok(false) => println!("No transition to {} available for {}", status, key),
+            Err(e) => println!("Failed to move {} to {}: {}", key, status, e),
+        }
+    }
+}
+
+async fn comment_issue_links(
+    client: &JiraClient,
+    project: &Project,
+    pull_request: &Value,
+    keys: &[String],
+) {
+    let repo = match project.github_repo {
+        Some(ref repo) => repo,
+        None => return,
+    };
+    let details = repo.split('/').collect::<Vec<&str>>();
+
+    let links = keys
+        .iter()
+        .map(|key| format!("- [{}]({})", key, client.issue_url(key)))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let result = octocrab::instance()
+        .issues(details[0], details[1])
+        .create_comment(
+            pull_request["number"].as_u64().unwrap_or_default(),
+            format!("🔗 Linked Jira issues:\n{}", links),
+        )
+        .await;
+
+    if let Err(e) = result {
+        println!("Failed to comment Jira links: {}", e);
+    }
+}
@@ -0,0 +1,200 @@
+use std::env;
+
+use ::rocket::serde::json::{json, Value};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+
+pub mod issues;
+pub mod rocket;
+pub mod webhooks;
+
+pub fn jira_handler(input: Value) {
+    ::rocket::tokio::spawn(async move {
+        if let Err(e) = webhooks::handle_jira_event(&input).await {
+            // Tell the project's channel when the issue belongs to a project
+            let manifest = crate::config::read_manifest();
+            let channel = input["issue"]["fields"]["project"]["key"]
+                .as_str()
+                .and_then(|key| crate::config::get_project_by_jira_project(&manifest, key))
+                .map(|project| project.slack_channel.as_str());
+
+            crate::error::report(&e, "Handling the Jira event", channel).await;
+        }
+    });
+}
+
+/// A client for the Jira REST API, configured with `JIRA_BASE_URL` and either
+/// `JIRA_USER` and `JIRA_API_TOKEN` (Jira Cloud) or just `JIRA_API_TOKEN` as a
+/// personal access token (Jira Server/Data Center).
+pub struct JiraClient {
+    base_url: String,
+    user: Option<String>,
+    token: String,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResults {
+    issues: Vec<Issue>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Issue {
+    pub key: String,
+    pub fields: IssueFields,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IssueFields {
+    pub summary: String,
+    pub status: IssueStatus,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IssueStatus {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Transitions {
+    transitions: Vec<Transition>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Transition {
+    id: String,
+    name: String,
+    to: Option<TransitionTarget>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TransitionTarget {
+    name: String,
+}
+
+impl JiraClient {
+    /// Returns `None` when Jira is not configured.
+    pub fn from_env() -> Option<JiraClient> {
+        dotenv::dotenv().ok();
+
+        let base_url = env::var("JIRA_BASE_URL").ok()?;
+        let token = env::var("JIRA_API_TOKEN").ok()?;
+
+        Some(JiraClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            user: env::var("JIRA_USER").ok(),
+            token,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    pub fn issue_url(&self, key: &str) -> String {
+        format!("{}/browse/{}", self.base_url, key)
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.user {
+            Some(ref user) => request.basic_auth(user, Some(&self.token)),
+            None => request.bearer_auth(&self.token),
+        }
+    }
+
+    /// Finds up to 50 issues matching `jql`.
+    pub async fn search(&self, jql: &str) -> Result<Vec<Issue>, reqwest::Error> {
+        let results = self
+            .authorize(
+                self.http
+                    .get(format!("{}/rest/api/2/search", self.base_url)),
+            )
+            .query(&[
+                ("jql", jql),
+                ("fields", "summary,status"),
+                ("maxResults", "50"),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<SearchResults>()
+            .await?;
+
+        Ok(results.issues)
+    }
+
+    /// Moves an issue to `status` if one of its available transitions leads there.
+    /// Returns `false` when no such transition exists, e.g. because the issue is
+    /// already in that status.
+    pub async fn transition_issue(&self, key: &str, status: &str) -> Result<bool, reqwest::Error> {
+        let url = format!("{}/rest/api/2/issue/{}/transitions", self.base_url, key);
+
+        let transitions = self
+            .authorize(self.http.get(&url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Transitions>()
+            .await?;
+
+        let transition = transitions.transitions.into_iter().find(|transition| {
+            let target = transition
+                .to
+                .as_ref()
+                .map(|to| to.name.as_str())
+                .unwrap_or(&transition.name);
+
+            target.eq_ignore_ascii_case(status)
+        });
+
+        let transition = match transition {
+            Some(transition) => transition,
+            None => return Ok(false),
+        };
+
+        self.authorize(self.http.post(&url))
+            .json(&json!({ "transition": { "id": transition.id } }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(true)
+    }
+}
+
+/// Finds keys of issues in `jira_project` (e.g. `CTRL-12`) in `text`. Matching
+/// ignores case so branch names like `ctrl-12-fix-login` are found too.
+pub fn find_issue_keys(jira_project: &str, text: &str) -> Vec<String> {
+    let prefix = format!("{}-", jira_project.to_uppercase());
+    let text = text.to_uppercase();
+    let bytes = text.as_bytes();
+
+    let mut keys = Vec::new();
+    let mut start = 0;
+
+    while let Some(position) = text[start..].find(&prefix) {
+        let begin = start + position;
+        let digits = begin + prefix.len();
+        let end = text[digits..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map(|end| digits + end)
+            .unwrap_or(text.len());
+
+        let at_boundary = begin == 0 || !bytes[begin - 1].is_ascii_alphanumeric();
+        if at_boundary && end > digits {
+            let key = text[begin..end].to_string();
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+
+        start = digits;
+    }
+
+    keys
+}
+
+/// Whether `key` looks like a Jira project key, e.g. `CTRL`.
+pub fn is_project_key(key: &str) -> bool {
+    let mut chars = key.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_uppercase())
+        && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
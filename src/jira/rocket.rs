@@ -0,0 +1,58 @@
+use std::env;
+
+use rocket::{
+    data::{self, Data, FromData, Limits},
+    http::Status,
+    outcome::Outcome,
+    serde::json::{serde_json, Value},
+    Request,
+};
+
+/// The JSON body of a Jira webhook delivery, only produced once `X-Hub-Signature`
+/// matches the raw body signed with `JIRA_WEBHOOK_SECRET`.
+#[derive(Debug)]
+pub struct SignedPayload(pub Value);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SignedPayload {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = request.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return Outcome::Failure((Status::PayloadTooLarge, ())),
+            Err(_) => return Outcome::Failure((Status::BadRequest, ())),
+        };
+
+        let secret = match env::var("JIRA_WEBHOOK_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret,
+            _ => {
+                println!("Rejected Jira delivery: JIRA_WEBHOOK_SECRET is not set");
+                return Outcome::Failure((Status::Unauthorized, ()));
+            }
+        };
+
+        let signature = request
+            .headers()
+            .get_one("X-Hub-Signature")
+            .and_then(|signature| signature.strip_prefix("sha256="));
+
+        match signature {
+            Some(signature) if crate::signature::verify(secret.as_bytes(), &body, signature) => {
+                match serde_json::from_slice(&body) {
+                    Ok(input) => Outcome::Success(SignedPayload(input)),
+                    Err(_) => Outcome::Failure((Status::BadRequest, ())),
+                }
+            }
+            Some(_) => {
+                println!("Rejected Jira delivery: signature mismatch");
+                Outcome::Failure((Status::Unauthorized, ()))
+            }
+            None => {
+                println!("Rejected Jira delivery: missing X-Hub-Signature");
+                Outcome::Failure((Status::Unauthorized, ()))
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use rocket::serde::json::Value;
+
+use crate::{
+    config::{get_project_by_jira_project, get_slack_by_jira_account_id, Manifest},
+    error::Result,
+    slack::handler::respond_http_text,
+};
+
+/// Comments longer than this are cut short in Slack.
+const MAX_COMMENT_LENGTH: usize = 300;
+
+pub async fn handle_jira_event(input: &Value) -> Result<()> {
+    let event = input["webhookEvent"].as_str().unwrap_or("");
+    let issue = &input["issue"];
+
+    println!(
+        "Received Jira event: {:?} for {:?}",
+        event,
+        issue["key"].as_str().unwrap_or("")
+    );
+
+    let jira_project = match issue["fields"]["project"]["key"].as_str() {
+        Some(jira_project) => jira_project,
+        None => return Ok(()),
+    };
+
+    let manifest = crate::config::read_manifest();
+    let project = match get_project_by_jira_project(&manifest, jira_project) {
+        Some(project) => project,
+        None => {
+            println!("No project found for Jira project: {}", jira_project);
+            return Ok(());
+        }
+    };
+
+    let messages = match event {
+        "jira:issue_created" => vec![issue_created(&manifest, input)],
+        "jira:issue_updated" => issue_updated(&manifest, input),
+        "comment_created" => vec![comment_created(input)],
+        _ => vec![],
+    };
+
+    for message in messages {
+        respond_http_text(&project.slack_channel, message).await?;
+    }
+
+    Ok(())
+}
+
+fn issue_created(manifest: &Manifest, input: &Value) -> String {
+    let issue = &input["issue"];
+    let mut text = format!(
+        "🆕 {} created {}: {}",
+        display_name(&input["user"]),
+        issue_link(issue),
+        issue["fields"]["summary"].as_str().unwrap_or("")
+    );
+
+    if !issue["fields"]["assignee"].is_null() {
+        text.push_str(&format!(
+            "\nAssigned to {}.",
+            mention(manifest, &issue["fields"]["assignee"])
+        ));
+    }
+
+    text
+}
+
+/// One message per status or assignee change, other field changes are ignored.
+fn issue_updated(manifest: &Manifest, input: &Value) -> Vec<String> {
+    let issue = &input["issue"];
+    let items = match input["changelog"]["items"].as_array() {
+        Some(items) => items,
+        None => return vec![],
+    };
+
+    items
+        .iter()
+        .filter_map(|item| match item["field"].as_str() {
+            Some("status") => Some(format!(
+                "🔀 {} moved {} from _{}_ to _{}_.",
+                display_name(&input["user"]),
+                issue_link(issue),
+                item["fromString"].as_str().unwrap_or("none"),
+                item["toString"].as_str().unwrap_or("none")
+            )),
+            Some("assignee") => match issue["fields"]["assignee"].is_null() {
+                true => Some(format!("👤 {} is now unassigned.", issue_link(issue))),
+                false => Some(format!(
+                    "👤 {} has been assigned to {}.",
+                    issue_link(issue),
+                    mention(manifest, &issue["fields"]["assignee"])
+                )),
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn comment_created(input: &Value) -> String {
+    let comment = &input["comment"];
+    let body = comment["body"].as_str().unwrap_or("");
+    let body = match body.char_indices().nth(MAX_COMMENT_LENGTH) {
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body.to_string(),
+    };
+
+    format!(
+        "💬 {} commented on {}:\n>{}",
+        display_name(&comment["author"]),
+        issue_link(&input["issue"]),
+        body.replace('\n', "\n>")
+    )
+}
+
+/// A Slack link to the issue, built from the site in the issue's API URL.
+fn issue_link(issue: &Value) -> String {
+    let key = issue["key"].as_str().unwrap_or("");
+
+    match issue["self"]
+        .as_str()
+        .and_then(|url| url.split_once("/rest/"))
+    {
+        Some((site, _)) => format!("<{}/browse/{}|{}>", site, key, key),
+        None => format!("`{}`", key),
+    }
+}
+
+fn display_name(user: &Value) -> String {
+    user["displayName"]
+        .as_str()
+        .unwrap_or("Someone")
+        .to_string()
+}
+
+/// Mentions the Slack user linked to a Jira user, falling back to their name.
+/// Jira Cloud identifies users by `accountId`, Jira Server by `name`.
+fn mention(manifest: &Manifest, user: &Value) -> String {
+    user["accountId"]
+        .as_str()
+        .or_else(|| user["name"].as_str())
+        .and_then(|account_id| get_slack_by_jira_account_id(manifest, account_id))
+        .map(|slack_id| format!("<@{}>", slack_id))
+        .unwrap_or_else(|| display_name(user))
+}
@@ -1,18 +1,23 @@
-#![feature(proc_macro_hygiene, decl_macro)]
+#![feature(proc_macro_hygiene)]
+// Rocket's `FromForm` derive still allows the removed `private_in_public` lint
+#![allow(renamed_and_removed_lints)]
 #[macro_use]
 extern crate rocket;
 extern crate reqwest;
 extern crate serde;
 extern crate toml;
 
-use config::read_manifest;
-use rocket::form::Form;
+use config::{load_manifest, state::load_state};
 use rocket::http::Status;
 use rocket::serde::json::{Json, Value};
 use serde::Serialize;
 
 mod config;
+mod error;
 mod github;
+mod jira;
+mod scheduler;
+mod signature;
 mod slack;
 
 #[derive(Serialize, Debug)]
@@ -28,6 +33,14 @@ pub enum Response {
     },
 }
 
+/// The answer to a Slack interaction: errors to show on a submitted form, or an
+/// empty 200 that closes it.
+#[derive(Responder)]
+enum InteractionResponse {
+    Errors(Json<Value>),
+    Done(()),
+}
+
 #[derive(FromForm, Clone)]
 pub struct Parameters {
     // Payload fields from "Preparing your app to receive Commands" section in documentation:
@@ -47,15 +60,38 @@ pub struct Parameters {
 }
 
 #[post("/slack", data = "<input>")]
-async fn slack_command(input: Form<Parameters>) -> Status {
-    // Return data, respond in background
-    Status::Accepted
+fn slack_command(input: slack::rocket::SignedCommand) -> Status {
+    slack::http_handler(input.0);
+
+    // Return data, respond in background. Slack expects a 200 here.
+    Status::Ok
+}
+
+#[post("/slack/interactive", data = "<input>")]
+fn slack_interaction(input: slack::rocket::SignedInteraction) -> InteractionResponse {
+    // Forms with mistakes are answered with the errors to show. Otherwise an empty
+    // 200 closes a submitted modal, replies are sent in the background.
+    match slack::http_interaction_handler(input.0) {
+        Some(response) => InteractionResponse::Errors(Json(response)),
+        None => InteractionResponse::Done(()),
+    }
 }
 
 #[post("/github", format = "application/json", data = "<input>")]
-fn github_command<'a>(input: Json<Value>, event: github::rocket::GitHubEvent) -> Status {
+fn github_command(
+    input: github::rocket::SignedPayload,
+    event: github::rocket::GitHubEvent,
+) -> Status {
     // Unwrap inner object.
-    github::github_handler(input.into_inner(), event);
+    github::github_handler(input.0, event);
+
+    // Return data, respond in background
+    Status::Accepted
+}
+
+#[post("/jira", format = "application/json", data = "<input>")]
+fn jira_webhook(input: jira::rocket::SignedPayload) -> Status {
+    jira::jira_handler(input.0);
 
     // Return data, respond in background
     Status::Accepted
@@ -70,16 +106,23 @@ fn not_found() -> &'static str {
 #[rocket::main]
 async fn main() {
     // Initialise manifest
-    read_manifest();
+    load_manifest();
+    load_state();
 
     // Initialise Octocrab
     github::setup_octocrab();
 
-    rocket::tokio::join!(
+    let (_, _, _, launched) = rocket::tokio::join!(
         slack::start(),
+        scheduler::start(),
+        config::watch::watch(),
         rocket::build()
-            .mount("/", routes![github_command, slack_command])
+            .mount(
+                "/",
+                routes![github_command, jira_webhook, slack_command, slack_interaction]
+            )
             .register("/", catchers![not_found])
             .launch()
     );
+    launched.expect("Failed to launch Rocket");
 }
@@ -0,0 +1,184 @@
+use chrono::{DateTime, Duration, Utc};
+use octocrab::{
+    models::{
+        pulls::{PullRequest, ReviewState},
+        IssueState,
+    },
+    params,
+};
+
+use crate::{
+    config::{
+        state::{read_state, update_state},
+        Project,
+    },
+    jira::JiraClient,
+    slack::handler::respond_http_text,
+};
+
+/// Posts a digest of everything since the last one to the project's channel.
+pub async fn send_digest(project_name: &str, project: &Project) {
+    let now = Utc::now();
+    let digest = build_digest(project_name, project, last_digest(project_name)).await;
+
+    if let Err(e) = respond_http_text(&project.slack_channel, digest).await {
+        println!("Failed to post digest for {}: {}", project_name, e);
+        return;
+    }
+
+    update_state(|state| state.last_digests.insert(project_name.to_string(), now));
+}
+
+/// When the last scheduled digest was posted, or a day ago if there hasn't been one.
+pub fn last_digest(project_name: &str) -> DateTime<Utc> {
+    read_state()
+        .last_digests
+        .get(project_name)
+        .copied()
+        .unwrap_or_else(|| Utc::now() - Duration::days(1))
+}
+
+/// Lists the pull requests opened, approved and merged since `since`, the ones
+/// still waiting on review, and the Jira issues that changed status.
+pub async fn build_digest(project_name: &str, project: &Project, since: DateTime<Utc>) -> String {
+    let mut text = format!(
+        "📰 *Digest for `{}`* since <!date^{}^{{date_short_pretty}} at {{time}}|{}>\n",
+        project_name,
+        since.timestamp(),
+        since.format("%Y-%m-%d %H:%M UTC")
+    );
+    let mut empty = true;
+
+    if let Some(ref repo) = project.github_repo {
+        match pull_request_sections(repo, since).await {
+            Ok(sections) => {
+                for (title, lines) in sections {
+                    if !lines.is_empty() {
+                        empty = false;
+                        text.push_str(&format!("\n*{}*\n{}\n", title, lines.join("\n")));
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Failed to list pull requests for {}: {}", repo, e);
+                text.push_str("\n_Unable to fetch pull requests from GitHub._\n");
+            }
+        }
+    }
+
+    if let Some(ref jira_project) = project.jira_project {
+        if let Some(client) = JiraClient::from_env() {
+            let jql = format!(
+                "project = {} AND status CHANGED AFTER -{}m ORDER BY updated DESC",
+                jira_project,
+                (Utc::now() - since).num_minutes().max(1)
+            );
+
+            match client.search(&jql).await {
+                Ok(issues) if !issues.is_empty() => {
+                    empty = false;
+                    text.push_str("\n*Jira issues moved*\n");
+                    for issue in issues {
+                        text.push_str(&format!(
+                            "• <{}|{}> {} → _{}_\n",
+                            client.issue_url(&issue.key),
+                            issue.key,
+                            issue.fields.summary,
+                            issue.fields.status.name
+                        ));
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    println!("Failed to search Jira for {}: {}", jira_project, e);
+                    text.push_str("\n_Unable to fetch issues from Jira._\n");
+                }
+            }
+        }
+    }
+
+    if empty {
+        text.push_str("\nNothing new to report. 😴");
+    }
+
+    text
+}
+
+async fn pull_request_sections(
+    repo: &str,
+    since: DateTime<Utc>,
+) -> octocrab::Result<Vec<(&'static str, Vec<String>)>> {
+    let details = repo.split('/').collect::<Vec<&str>>();
+    let instance = octocrab::instance();
+    let pr_handler = instance.pulls(details[0], details[1]);
+
+    let pull_requests = pr_handler
+        .list()
+        .state(params::State::All)
+        .sort(params::pulls::Sort::Updated)
+        .direction(params::Direction::Descending)
+        .per_page(100)
+        .send()
+        .await?
+        .items;
+
+    let mut opened = vec![];
+    let mut approved = vec![];
+    let mut merged = vec![];
+    let mut waiting = vec![];
+
+    for pull_request in &pull_requests {
+        let line = format!(
+            "• <https://github.com/{repo}/pull/{num}|#{num} {title}>",
+            repo = repo,
+            num = pull_request.number,
+            title = pull_request.title.as_deref().unwrap_or("")
+        );
+
+        if is_after(pull_request.created_at, since) {
+            opened.push(line.clone());
+        }
+
+        if is_after(pull_request.merged_at, since) {
+            merged.push(line.clone());
+        }
+
+        if is_after(pull_request.updated_at, since) {
+            let reviews = pr_handler
+                .list_reviews(pull_request.number)
+                .send()
+                .await?
+                .items;
+
+            if reviews.iter().any(|review| {
+                review.state == Some(ReviewState::Approved) && is_after(review.submitted_at, since)
+            }) {
+                approved.push(line.clone());
+            }
+        }
+
+        if is_waiting(pull_request) {
+            waiting.push(line);
+        }
+    }
+
+    Ok(vec![
+        ("Opened", opened),
+        ("Approved", approved),
+        ("Merged", merged),
+        ("Still waiting on review", waiting),
+    ])
+}
+
+fn is_after(time: Option<DateTime<Utc>>, since: DateTime<Utc>) -> bool {
+    time.is_some_and(|time| time >= since)
+}
+
+fn is_waiting(pull_request: &PullRequest) -> bool {
+    pull_request.state == Some(IssueState::Open)
+        && !pull_request.draft.unwrap_or(false)
+        && pull_request
+            .requested_reviewers
+            .as_ref()
+            .is_some_and(|reviewers| !reviewers.is_empty())
+}
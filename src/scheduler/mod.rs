@@ -0,0 +1,88 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use tokio_cron_scheduler::{Job, JobScheduler};
+
+pub mod digest;
+pub mod reminders;
+
+/// Starts a job that runs every minute and does whatever each project has
+/// scheduled for that minute. Schedules are read from the manifest on every tick,
+/// so changes apply without a restart.
+pub async fn start() {
+    let scheduler = JobScheduler::new()
+        .await
+        .expect("Failed to create scheduler");
+
+    let job = Job::new_async("0 * * * * *", |_, _| {
+        Box::pin(async move {
+            tick(Utc::now()).await;
+        })
+    })
+    .expect("Failed to create scheduler job");
+
+    scheduler
+        .add(job)
+        .await
+        .expect("Failed to add scheduler job");
+    scheduler.start().await.expect("Failed to start scheduler");
+}
+
+async fn tick(now: DateTime<Utc>) {
+    let manifest = crate::config::read_manifest();
+
+    for (project_name, project) in manifest.projects {
+        if let Some(ref reminders) = project.reminders {
+            if is_due(&project_name, &reminders.schedule, None, now) {
+                let project = project.clone();
+                ::rocket::tokio::spawn(async move {
+                    reminders::send_reminders(&project).await;
+                });
+            }
+        }
+
+        if let Some(ref digest) = project.digest {
+            if is_due(
+                &project_name,
+                &digest.schedule,
+                digest.timezone.as_deref(),
+                now,
+            ) {
+                let project_name = project_name.clone();
+                let project = project.clone();
+                ::rocket::tokio::spawn(async move {
+                    digest::send_digest(&project_name, &project).await;
+                });
+            }
+        }
+    }
+}
+
+/// Whether `schedule` fires in the minute leading up to `now`, evaluating it in
+/// `timezone` (UTC if not given).
+fn is_due(project_name: &str, schedule: &str, timezone: Option<&str>, now: DateTime<Utc>) -> bool {
+    let schedule = match cron::Schedule::from_str(schedule) {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            println!(
+                "Invalid schedule `{}` for project {}: {}",
+                schedule, project_name, e
+            );
+            return false;
+        }
+    };
+
+    let timezone = match timezone.unwrap_or("UTC").parse::<Tz>() {
+        Ok(timezone) => timezone,
+        Err(e) => {
+            println!("Invalid timezone for project {}: {}", project_name, e);
+            return false;
+        }
+    };
+
+    schedule
+        .after(&(now - Duration::minutes(1)).with_timezone(&timezone))
+        .next()
+        .is_some_and(|next| next <= now)
+}
@@ -0,0 +1,103 @@
+use chrono::{Duration, Utc};
+use octocrab::params;
+
+use crate::{
+    config::{
+        get_slack_by_github_username,
+        state::{pull_request_key, update_state},
+        Project,
+    },
+    slack::handler::respond_http_text,
+};
+
+/// Pings the requested reviewers of pull requests that have been waiting on
+/// review for longer than the project's threshold.
+pub async fn send_reminders(project: &Project) {
+    let (repo, reminders) = match (&project.github_repo, &project.reminders) {
+        (Some(repo), Some(reminders)) => (repo, reminders),
+        _ => return,
+    };
+    let details = repo.split('/').collect::<Vec<&str>>();
+
+    let pull_requests = octocrab::instance()
+        .pulls(details[0], details[1])
+        .list()
+        .state(params::State::Open)
+        .per_page(100)
+        .send()
+        .await;
+
+    let pull_requests = match pull_requests {
+        Ok(pull_requests) => pull_requests,
+        Err(e) => {
+            println!("Failed to list pull requests for {}: {}", repo, e);
+            return;
+        }
+    };
+
+    let manifest = crate::config::read_manifest();
+    let now = Utc::now();
+    let threshold = Duration::hours(reminders.stale_after_hours);
+
+    // Forget snoozes that have run out
+    let snoozed = update_state(|state| {
+        state.snoozed.retain(|_, until| *until > now);
+        state.snoozed.clone()
+    });
+
+    let stale = pull_requests
+        .items
+        .iter()
+        .filter(|pull_request| !pull_request.draft.unwrap_or(false))
+        .filter(|pull_request| {
+            !snoozed.contains_key(&pull_request_key(repo, pull_request.number))
+        })
+        .filter_map(|pull_request| {
+            let waiting = now - pull_request.created_at?;
+            let reviewers = pull_request.requested_reviewers.as_ref()?;
+
+            if waiting < threshold || reviewers.is_empty() {
+                return None;
+            }
+
+            let mentions = reviewers
+                .iter()
+                .map(|reviewer| match get_slack_by_github_username(&manifest, &reviewer.login) {
+                    Some(slack) => format!("<@{}>", slack),
+                    None => format!("@{}", reviewer.login),
+                })
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            Some(format!(
+                "• <https://github.com/{repo}/pull/{num}|#{num} {title}> has been waiting {waiting}: {mentions}",
+                repo = repo,
+                num = pull_request.number,
+                title = pull_request.title.as_deref().unwrap_or(""),
+                waiting = format_waiting(waiting),
+                mentions = mentions
+            ))
+        })
+        .collect::<Vec<String>>();
+
+    if stale.is_empty() {
+        return;
+    }
+
+    let _ = respond_http_text(
+        &project.slack_channel,
+        format!(
+            "⏰ These PRs on <https://github.com/{repo}|{repo}> are waiting on review:\n{prs}",
+            repo = repo,
+            prs = stale.join("\n")
+        ),
+    )
+    .await;
+}
+
+fn format_waiting(waiting: Duration) -> String {
+    match waiting.num_hours() {
+        hours if hours < 48 => format!("{} hours", hours),
+        hours => format!("{} days", hours / 24),
+    }
+}
@@ -0,0 +1,47 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Checks a hex encoded HMAC-SHA256 `signature` of `payload` in constant time.
+pub fn verify(secret: &[u8], payload: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    let mut mac = match HmacSha256::new_from_slice(secret) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(payload);
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from GitHub's guide to validating webhook deliveries
+    const SECRET: &[u8] = b"It's a Secret to Everybody";
+    const SIGNATURE: &str = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn accepts_a_matching_signature() {
+        assert!(verify(SECRET, b"Hello, World!", SIGNATURE));
+    }
+
+    #[test]
+    fn rejects_a_different_payload_or_secret() {
+        assert!(!verify(SECRET, b"Hello, World?", SIGNATURE));
+        assert!(!verify(b"another secret", b"Hello, World!", SIGNATURE));
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        assert!(!verify(SECRET, b"Hello, World!", ""));
+        assert!(!verify(SECRET, b"Hello, World!", "not hex"));
+        assert!(!verify(SECRET, b"Hello, World!", &SIGNATURE[..32]));
+    }
+}
@@ -0,0 +1,496 @@
+use super::handler;
+use super::parser::{self, Arg, Flag, ParseError, Spec};
+use super::permissions::{self, Role};
+use super::reply::ReplySink;
+use crate::config::{get_project_name_by_slack_channel, Actor, JiraStatuses};
+
+/// The `/ctrl` commands, used to parse them and to generate the help text.
+pub static COMMANDS: &[Spec] = &[
+    Spec::new("help", "Show this help guide.").args(&[Arg::optional("command")]),
+    Spec::new("list", "List all projects."),
+    Spec::new(
+        "project",
+        "Show information about the current channel's project.",
+    ),
+    Spec::new(
+        "create",
+        "Create a new project, automatically assigning it to this channel. Without a name, opens a form for the project's settings.",
+    )
+    .args(&[Arg::optional("project_name")])
+    .flags(&[Flag {
+        name: "github",
+        value: "repo_name",
+        about: "Also set the GitHub repository for the project.",
+    }]),
+    Spec::new("edit", "Edit this channel's project in a form."),
+    Spec::new("delete", "Delete a project.").args(&[Arg::required("project_name")]),
+    Spec::new("add", "Add a user as a manager to this project").args(&[Arg::required("@user")]),
+    Spec::new("remove", "Remove a user as a manager from this project")
+        .args(&[Arg::required("@user")]),
+    Spec::new(
+        "github",
+        "Set the GitHub repository for this project (PRs will be automatically merged, assigned, etc.).",
+    )
+    .args(&[Arg::required("repo_name")]),
+    Spec::new(
+        "jira",
+        "Link this project to a Jira project. Issue keys in PR titles and branches are linked and moved as the PR progresses.",
+    )
+    .args(&[Arg::required("jira_key")])
+    .flags(&[
+        Flag {
+            name: "opened",
+            value: "status",
+            about: "Move issues to this status when their PR is opened.",
+        },
+        Flag {
+            name: "approved",
+            value: "status",
+            about: "Move issues to this status when their PR is approved.",
+        },
+        Flag {
+            name: "merged",
+            value: "status",
+            about: "Move issues to this status when their PR is merged.",
+        },
+    ]),
+    Spec::new("branches", "View and edit how PRs are handled by base branch.").subcommands(&[
+        Spec::new("list", "Show this project's branch policy, checked in order."),
+        Spec::new(
+            "set",
+            "Set the rule for base branches matching a glob such as `release/*`. New rules are checked first. The mode is one of `review-merge`, `review`, `merge` or `notify`.",
+        )
+        .args(&[Arg::required("pattern"), Arg::required("mode")]),
+        Spec::new("remove", "Remove the rule for a pattern.").args(&[Arg::required("pattern")]),
+        Spec::new("reset", "Go back to the default branch policy."),
+    ]),
+    Spec::new(
+        "merge",
+        "Show or change how approved PRs are merged automatically.",
+    )
+    .flags(&[
+        Flag {
+            name: "method",
+            value: "merge|squash|rebase",
+            about: "The merge method to use.",
+        },
+        Flag {
+            name: "approvals",
+            value: "count",
+            about: "How many distinct approvals are required.",
+        },
+        Flag {
+            name: "owner-approval",
+            value: "yes|no",
+            about: "Whether a project owner must be one of the approvers.",
+        },
+        Flag {
+            name: "checks",
+            value: "check,...|all",
+            about: "The checks that must pass before merging, or `all` to require every reported check.",
+        },
+    ]),
+    Spec::new(
+        "reviewers",
+        "Show or change how reviewers are picked from the project owners and managers.",
+    )
+    .flags(&[
+        Flag {
+            name: "strategy",
+            value: "all|random|round-robin|least-loaded",
+            about: "Request everyone, or pick reviewers at random, in turn, or by fewest open review requests.",
+        },
+        Flag {
+            name: "count",
+            value: "count",
+            about: "How many reviewers to pick, for strategies other than `all`.",
+        },
+    ]),
+    Spec::new("digest", "Post project digests.").subcommands(&[Spec::new(
+        "now",
+        "Post a digest of this project's activity since the last scheduled one.",
+    )]),
+    Spec::new(
+        "history",
+        "Show recent config changes, or only those to a project.",
+    )
+    .args(&[Arg::optional("project_name")]),
+    Spec::new("rollback", "Revert a config change listed by `/ctrl history`.")
+        .args(&[Arg::required("change_id")]),
+    Spec::new("me", "Manage your linked accounts.").subcommands(&[
+        Spec::new("github", "Set your GitHub username.")
+            .args(&[Arg::required("github_username")])
+            .flags(&[Flag {
+                name: "for",
+                value: "@user",
+                about: "Link the username for someone else. Managers only, and the only way to link the usernames of managers and project owners.",
+            }]),
+        Spec::new(
+            "jira",
+            "Set your Jira account ID (or username on Jira Server) so you are mentioned in Jira notifications.",
+        )
+        .args(&[Arg::required("account_id")]),
+    ]),
+];
+
+/// A parsed `/ctrl` command.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help {
+        command: Option<String>,
+    },
+    List,
+    Project,
+    Create {
+        project_name: Option<String>,
+        github_repo: Option<String>,
+    },
+    Edit,
+    Delete {
+        project_name: String,
+    },
+    Add {
+        user: String,
+    },
+    Remove {
+        user: String,
+    },
+    GitHub {
+        repo_name: String,
+    },
+    Jira {
+        jira_key: String,
+        statuses: JiraStatuses,
+    },
+    BranchesList,
+    BranchesSet {
+        pattern: String,
+        mode: String,
+    },
+    BranchesRemove {
+        pattern: String,
+    },
+    BranchesReset,
+    Merge {
+        method: Option<String>,
+        approvals: Option<String>,
+        owner_approval: Option<String>,
+        checks: Option<String>,
+    },
+    Reviewers {
+        strategy: Option<String>,
+        count: Option<String>,
+    },
+    DigestNow,
+    History {
+        project_name: Option<String>,
+    },
+    Rollback {
+        change_id: String,
+    },
+    MeGitHub {
+        github_username: String,
+        user: Option<String>,
+    },
+    MeJira {
+        account_id: String,
+    },
+}
+
+impl Command {
+    pub fn parse(text: &str) -> Result<Command, ParseError> {
+        let matches = parser::parse(COMMANDS, "/ctrl", text)?;
+
+        match matches.path.as_slice() {
+            ["help"] => Ok(Command::Help {
+                command: matches.get("command"),
+            }),
+            ["list"] => Ok(Command::List),
+            ["project"] => Ok(Command::Project),
+            ["create"] => Ok(Command::Create {
+                project_name: matches.get("project_name"),
+                github_repo: matches.get("github"),
+            }),
+            ["edit"] => Ok(Command::Edit),
+            ["delete"] => Ok(Command::Delete {
+                project_name: matches.value("project_name"),
+            }),
+            ["add"] => Ok(Command::Add {
+                user: matches.value("@user"),
+            }),
+            ["remove"] => Ok(Command::Remove {
+                user: matches.value("@user"),
+            }),
+            ["github"] => Ok(Command::GitHub {
+                repo_name: matches.value("repo_name"),
+            }),
+            ["jira"] => Ok(Command::Jira {
+                jira_key: matches.value("jira_key"),
+                statuses: JiraStatuses {
+                    opened: matches.get("opened"),
+                    approved: matches.get("approved"),
+                    merged: matches.get("merged"),
+                },
+            }),
+            ["branches", "list"] => Ok(Command::BranchesList),
+            ["branches", "set"] => Ok(Command::BranchesSet {
+                pattern: matches.value("pattern"),
+                mode: matches.value("mode"),
+            }),
+            ["branches", "remove"] => Ok(Command::BranchesRemove {
+                pattern: matches.value("pattern"),
+            }),
+            ["branches", "reset"] => Ok(Command::BranchesReset),
+            ["merge"] => Ok(Command::Merge {
+                method: matches.get("method"),
+                approvals: matches.get("approvals"),
+                owner_approval: matches.get("owner-approval"),
+                checks: matches.get("checks"),
+            }),
+            ["reviewers"] => Ok(Command::Reviewers {
+                strategy: matches.get("strategy"),
+                count: matches.get("count"),
+            }),
+            ["digest", "now"] => Ok(Command::DigestNow),
+            ["history"] => Ok(Command::History {
+                project_name: matches.get("project_name"),
+            }),
+            ["rollback"] => Ok(Command::Rollback {
+                change_id: matches.value("change_id"),
+            }),
+            ["me", "github"] => Ok(Command::MeGitHub {
+                github_username: matches.value("github_username"),
+                user: matches.get("for"),
+            }),
+            ["me", "jira"] => Ok(Command::MeJira {
+                account_id: matches.value("account_id"),
+            }),
+            path => Err(ParseError::UnknownCommand(path.join(" "))),
+        }
+    }
+}
+
+/// Who sent a command and where, independent of how it arrived.
+pub struct CommandContext {
+    pub user_id: String,
+    pub user_name: String,
+    pub channel_id: String,
+    /// The command as typed, without `/ctrl`.
+    pub text: String,
+    /// Lets the command open a modal, when it arrived with one.
+    pub trigger_id: Option<String>,
+    /// The project linked to `channel_id`, if any.
+    pub project: Option<String>,
+    /// The user's role in `project`.
+    pub role: Role,
+}
+
+impl CommandContext {
+    pub fn new(
+        user_id: String,
+        user_name: String,
+        channel_id: String,
+        text: String,
+        trigger_id: Option<String>,
+    ) -> CommandContext {
+        let manifest = crate::config::read_manifest();
+        let project = get_project_name_by_slack_channel(&manifest, &channel_id).map(String::from);
+        let role = Role::of(&manifest, &user_id, project.as_deref());
+
+        CommandContext {
+            user_id,
+            user_name,
+            channel_id,
+            text,
+            trigger_id,
+            project,
+            role,
+        }
+    }
+
+    /// The user running the command, as recorded with any change it makes.
+    pub fn actor(&self) -> Actor {
+        Actor {
+            command: Some(format!("/ctrl {}", self.text.trim())),
+            ..Actor::new(&self.user_id, &self.user_name)
+        }
+    }
+}
+
+/// Parses and runs the text of a `/ctrl` command.
+pub async fn run(ctx: &CommandContext, reply: &dyn ReplySink) {
+    match Command::parse(&ctx.text) {
+        Ok(command) => dispatch(ctx, command, reply).await,
+        Err(ParseError::Empty) => handler::not_enough_arguments(reply).await,
+        Err(ParseError::UnknownCommand(_)) => handler::command_not_found(reply).await,
+        Err(ParseError::Syntax(message)) => handler::invalid_syntax(reply, &message).await,
+        Err(ParseError::Usage { message, usage }) => {
+            handler::invalid_usage(reply, &message, &usage).await
+        }
+    }
+}
+
+pub async fn dispatch(ctx: &CommandContext, command: Command, reply: &dyn ReplySink) {
+    if let Err(message) = permissions::check(ctx, &command) {
+        return reply.text(message).await;
+    }
+
+    match command {
+        Command::Help { command } => handler::help(reply, command.as_deref()).await,
+        Command::List => handler::list(reply).await,
+        Command::Create {
+            project_name: Some(project_name),
+            github_repo,
+        } => handler::create(ctx, reply, &project_name, github_repo).await,
+        Command::Create {
+            project_name: None,
+            github_repo,
+        } => handler::create_form(ctx, reply, github_repo).await,
+        Command::Edit => match &ctx.project {
+            Some(project) => handler::edit(ctx, reply, project).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::Delete { project_name } => handler::delete(ctx, reply, &project_name).await,
+        Command::MeGitHub {
+            github_username,
+            user,
+        } => handler::me_github(ctx, reply, &github_username, user).await,
+        Command::MeJira { account_id } => handler::me_jira(ctx, reply, &account_id).await,
+        Command::History { project_name } => handler::history(reply, project_name).await,
+        Command::Rollback { change_id } => handler::rollback(ctx, reply, &change_id).await,
+        Command::Project => match &ctx.project {
+            Some(project) => handler::project(reply, project).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::Add { user } => match &ctx.project {
+            Some(project) => handler::add(ctx, reply, project, &user).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::Remove { user } => match &ctx.project {
+            Some(project) => handler::remove(ctx, reply, project, &user).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::GitHub { repo_name } => match &ctx.project {
+            Some(project) => handler::github(ctx, reply, project, &repo_name).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::BranchesList => match &ctx.project {
+            Some(project) => handler::branches_list(reply, project).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::BranchesSet { pattern, mode } => match &ctx.project {
+            Some(project) => handler::branches_set(ctx, reply, project, &pattern, &mode).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::BranchesRemove { pattern } => match &ctx.project {
+            Some(project) => handler::branches_remove(ctx, reply, project, &pattern).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::BranchesReset => match &ctx.project {
+            Some(project) => handler::branches_reset(ctx, reply, project).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::Merge {
+            method,
+            approvals,
+            owner_approval,
+            checks,
+        } => match &ctx.project {
+            Some(project) => {
+                handler::merge(
+                    ctx,
+                    reply,
+                    project,
+                    method,
+                    approvals,
+                    owner_approval,
+                    checks,
+                )
+                .await
+            }
+            None => handler::project_not_found(reply).await,
+        },
+        Command::Reviewers { strategy, count } => match &ctx.project {
+            Some(project) => handler::reviewers(ctx, reply, project, strategy, count).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::DigestNow => match &ctx.project {
+            Some(project) => handler::digest_now(reply, project).await,
+            None => handler::project_not_found(reply).await,
+        },
+        Command::Jira { jira_key, statuses } => match &ctx.project {
+            Some(project) => handler::jira(ctx, reply, project, &jira_key, statuses).await,
+            None => handler::project_not_found(reply).await,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slack::reply::RecordingSink;
+
+    fn context(text: &str) -> CommandContext {
+        CommandContext {
+            user_id: "U1".to_string(),
+            user_name: "user".to_string(),
+            channel_id: "C1".to_string(),
+            text: text.to_string(),
+            trigger_id: None,
+            project: None,
+            role: Role::Member,
+        }
+    }
+
+    async fn reply_to(text: &str) -> String {
+        let reply = RecordingSink::default();
+        run(&context(text), &reply).await;
+        reply.text()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            Command::parse("branches set \"release/*\" review"),
+            Ok(Command::BranchesSet {
+                pattern: "release/*".to_string(),
+                mode: "review".to_string(),
+            })
+        );
+        assert_eq!(
+            Command::parse("me github octocat --for <@U2>"),
+            Ok(Command::MeGitHub {
+                github_username: "octocat".to_string(),
+                user: Some("<@U2>".to_string()),
+            })
+        );
+    }
+
+    #[rocket::async_test]
+    async fn answers_mistakes_with_help() {
+        assert_eq!(
+            reply_to("").await,
+            "Not enough arguments. Use `/ctrl help` for a list of commands."
+        );
+        assert_eq!(
+            reply_to("deploy").await,
+            "Invalid command. Use `/ctrl help` for a list of commands."
+        );
+        assert_eq!(
+            reply_to("github \"octo/ctrl").await,
+            "Missing closing `\"`. Use `/ctrl help` for a list of commands."
+        );
+        assert_eq!(
+            reply_to("delete").await,
+            "Missing `<project_name>`.\nUsage:\n`/ctrl delete <project_name>`"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn shows_help_for_a_command() {
+        let help = reply_to("help rollback").await;
+
+        assert!(help.contains("- `/ctrl rollback <change_id>`: Revert a config change"));
+        assert!(!help.contains("/ctrl list"));
+    }
+}
@@ -1,478 +1,1135 @@
 use std::env;
 
-use indoc::indoc;
-use rocket::serde::json::serde_json;
 use slack_rust::{
     block::{
-        self,
         block_elements::{BlockElement, ButtonElement},
         block_object::{TextBlockObject, TextBlockType},
         block_section::SectionBlock,
         blocks::Block,
     },
-    chat::post_message::{post_message, PostMessageRequest, PostMessageResponse},
-    http_client::{default_client, SlackWebAPIClient},
-    socket::socket_mode::SocketMode,
+    chat::{
+        post_ephemeral::{post_ephemeral, PostEphemeralRequest, PostEphemeralResponse},
+        post_message::{post_message, PostMessageRequest, PostMessageResponse},
+    },
+    http_client::default_client,
 };
 
+use super::{
+    command::{CommandContext, COMMANDS},
+    modal,
+    permissions::Role,
+    reply::ReplySink,
+};
 use crate::config::{
-    get_slack_by_github_username, get_user_by_github_username, get_user_by_slack_id,
-    get_user_by_slack_mention, set_user_github_username,
+    audit::{self, Entry, Target},
+    branches::BranchRule,
+    get_project_name_by_slack_channel, get_slack_by_github_username, get_user_by_github_username,
+    get_user_by_slack_mention, set_user_github_username, set_user_jira_account_id,
+    try_update_manifest, update_manifest,
+    validate::is_repo_slug,
+    Change, ChangeStatus, JiraStatuses, Manifest, MergeMethod, Project, ReviewerStrategy,
 };
+use crate::error::{self, Error};
+
+/// How many changes `/ctrl history` shows.
+const HISTORY_LENGTH: usize = 10;
+/// How many changed lines `/ctrl history` shows for each part of a change.
+const HISTORY_LINES: usize = 8;
 
 pub async fn respond_http_text(
-    channel_id: &String,
+    channel_id: &str,
     text: String,
-) -> Result<PostMessageResponse, slack_rust::error::Error> {
-    let slack_bot_token = env::var("SLACK_BOT_TOKEN").expect("slack bot token is not set.");
-    let request = PostMessageRequest::builder(channel_id.clone())
+) -> error::Result<PostMessageResponse> {
+    let slack_bot_token = env::var("SLACK_BOT_TOKEN")
+        .map_err(|_| Error::Slack("SLACK_BOT_TOKEN is not set".to_string()))?;
+    let request = PostMessageRequest::builder(channel_id.to_string())
         .text(text.clone())
         .build();
 
-    post_message(&default_client(), &request, &slack_bot_token).await
+    Ok(post_message(&default_client(), &request, &slack_bot_token).await?)
 }
 
 pub async fn respond_http_blocks(
-    channel_id: &String,
+    channel_id: &str,
     blocks: Vec<Block>,
-) -> Result<PostMessageResponse, slack_rust::error::Error> {
-    let slack_bot_token = env::var("SLACK_BOT_TOKEN").expect("slack bot token is not set.");
-    let request = PostMessageRequest::builder(channel_id.clone())
+) -> error::Result<PostMessageResponse> {
+    let slack_bot_token = env::var("SLACK_BOT_TOKEN")
+        .map_err(|_| Error::Slack("SLACK_BOT_TOKEN is not set".to_string()))?;
+    let request = PostMessageRequest::builder(channel_id.to_string())
         .blocks(blocks)
         .build();
 
-    post_message(&default_client(), &request, &slack_bot_token).await
+    Ok(post_message(&default_client(), &request, &slack_bot_token).await?)
 }
 
-async fn respond_text<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
+/// Posts a message in `channel_id` that only `user_id` can see.
+pub async fn respond_ephemeral(
+    channel_id: &str,
+    user_id: &str,
     text: String,
-) -> Result<PostMessageResponse, slack_rust::error::Error> {
-    let request = PostMessageRequest::builder(channel_id.clone())
-        .text(text.clone())
-        .build();
-
-    post_message(&socket_mode.api_client, &request, &socket_mode.bot_token).await
+) -> error::Result<PostEphemeralResponse> {
+    let slack_bot_token = env::var("SLACK_BOT_TOKEN")
+        .map_err(|_| Error::Slack("SLACK_BOT_TOKEN is not set".to_string()))?;
+    let request = PostEphemeralRequest {
+        channel: channel_id.to_string(),
+        user: user_id.to_string(),
+        text,
+        ..Default::default()
+    };
+
+    Ok(post_ephemeral(&default_client(), &request, &slack_bot_token).await?)
 }
 
-async fn respond_blocks<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
-    blocks: Vec<Block>,
-) -> Result<PostMessageResponse, slack_rust::error::Error> {
-    let request = PostMessageRequest::builder(channel_id.clone())
-        .blocks(blocks)
-        .build();
+pub async fn command_not_found(reply: &dyn ReplySink) {
+    reply
+        .text("Invalid command. Use `/ctrl help` for a list of commands.".to_string())
+        .await;
+}
 
-    post_message(&socket_mode.api_client, &request, &socket_mode.bot_token).await
+pub async fn project_not_found(reply: &dyn ReplySink) {
+    reply
+        .text("Project not found. Use `/ctrl list` for a list of projects.".to_string())
+        .await;
 }
 
-pub async fn command_not_found<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
-) {
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        "Invalid command. Use `/ctrl help` for a list of commands.".to_string(),
-    )
-    .await;
+pub async fn not_enough_arguments(reply: &dyn ReplySink) {
+    reply
+        .text("Not enough arguments. Use `/ctrl help` for a list of commands.".to_string())
+        .await;
 }
 
-pub async fn project_not_found<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
-) {
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        "Project not found. Use `/ctrl list` for a list of projects.".to_string(),
-    )
-    .await;
+pub async fn invalid_syntax(reply: &dyn ReplySink, message: &str) {
+    reply
+        .text(format!(
+            "{} Use `/ctrl help` for a list of commands.",
+            message
+        ))
+        .await;
 }
 
-pub async fn not_enough_arguments<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
-) {
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        "Not enough arguments. Use `/ctrl help` for a list of commands.".to_string(),
-    )
-    .await;
+pub async fn invalid_usage(reply: &dyn ReplySink, message: &str, usage: &str) {
+    reply.text(format!("{}\nUsage:\n{}", message, usage)).await;
 }
 
-pub async fn user_not_linked<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
-) {
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        "This user must link their GitHub account first. Use `/ctrl me github <github_username>`."
-            .to_string(),
-    )
-    .await;
+pub async fn user_not_linked(reply: &dyn ReplySink) {
+    reply
+        .text(
+            "This user must link their GitHub account first. Use `/ctrl me github <github_username>`."
+                .to_string(),
+        )
+        .await;
 }
 
-pub async fn help<S: SlackWebAPIClient>(socket_mode: &SocketMode<S>, channel_id: &String) {
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        indoc! {"
-            ⛑️ Here's a simple help guide for all the commands available.
- 
-            - /ctrl help: Show this help guide.
-            - /ctrl list: List all projects.
-            - /ctrl project: Show information about the current channel's project.
-            - /ctrl create <project_name>: Create a new project, automatically assigning it to this channel and adding you as a manager.
-            - /ctrl add <@user>: Add a user as a manager to this project
-            - /ctrl remove <@user>: Remove a user as a manager from this project
-            - /ctrl github <repo_name>: Set the GitHub repository for this project (PRs will be automatically merged, assigned, etc.).
-            - /ctrl me github <github_username>: Set your GitHub username.
-            "}.to_string(),
-    )
-    .await;
+pub async fn help(reply: &dyn ReplySink, command: Option<&str>) {
+    let specs = match command {
+        Some(command) => match COMMANDS.iter().find(|spec| spec.name == command) {
+            Some(spec) => std::slice::from_ref(spec),
+            None => return command_not_found(reply).await,
+        },
+        None => COMMANDS,
+    };
+
+    let help = specs
+        .iter()
+        .flat_map(|spec| spec.help("/ctrl"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    reply
+        .text(format!(
+            "⛑️ Here's a simple help guide for all the commands available.\n\n{}",
+            help
+        ))
+        .await;
 }
 
-pub async fn list<S: SlackWebAPIClient>(socket_mode: &SocketMode<S>, channel_id: &String) {
+pub async fn list(reply: &dyn ReplySink) {
     let manifest = crate::config::read_manifest();
     let projects = manifest.projects.clone();
     let managers = manifest.managers.clone().join(", ");
 
-    let _ = respond_blocks(
-        socket_mode,
-        channel_id,
-        vec![Block::SectionBlock(SectionBlock {
-            text: Some(
-                TextBlockObject::builder(
-                    TextBlockType::Mrkdwn,
-                    format!(
-                        "Global project managers: {}.\nHere's a list of all projects.",
-                        managers
-                    ),
-                )
-                .build(),
-            ),
-            ..Default::default()
-        })]
-        .into_iter()
-        .chain(
-            projects
-                .into_iter()
-                .map(|(name, project)| {
-                    let project_owners = project
-                        .project_owners
-                        .iter()
-                        .map(|github_username| {
-                            get_user_by_github_username(&manifest, github_username)
-                        })
-                        .filter(|name| name.is_some())
-                        .map(|f| f.unwrap().github_username.clone())
-                        .collect::<Vec<_>>()
-                        .join(", ");
-
-                    match project.github_repo {
-                        Some(repo) => Block::SectionBlock(SectionBlock {
-                            text: Some(
-                                TextBlockObject::builder(
-                                    TextBlockType::Mrkdwn,
-                                    format!(
-                                        "{} in <#{}>.\nProject owners: {}",
-                                        name, project.slack_channel, project_owners
-                                    ),
-                                )
-                                .build(),
-                            ),
-                            accessory: Some(BlockElement::ButtonElement(
-                                ButtonElement::builder(
+    reply
+        .blocks(
+            vec![Block::SectionBlock(SectionBlock {
+                text: Some(
+                    TextBlockObject::builder(
+                        TextBlockType::Mrkdwn,
+                        format!(
+                            "Global project managers: {}.\nHere's a list of all projects.",
+                            managers
+                        ),
+                    )
+                    .build(),
+                ),
+                ..Default::default()
+            })]
+            .into_iter()
+            .chain(
+                projects
+                    .into_iter()
+                    .map(|(name, project)| {
+                        let project_owners = project
+                            .project_owners
+                            .iter()
+                            .map(|github_username| {
+                                get_user_by_github_username(&manifest, github_username)
+                            })
+                            .filter_map(|profile| profile.map(|f| f.github_username.clone()))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+
+                        match project.github_repo {
+                            Some(repo) => Block::SectionBlock(SectionBlock {
+                                text: Some(
+                                    TextBlockObject::builder(
+                                        TextBlockType::Mrkdwn,
+                                        format!(
+                                            "{} in <#{}>.\nProject owners: {}",
+                                            name, project.slack_channel, project_owners
+                                        ),
+                                    )
+                                    .build(),
+                                ),
+                                accessory: Some(BlockElement::ButtonElement(
+                                    ButtonElement::builder(
+                                        TextBlockObject::builder(
+                                            TextBlockType::PlainText,
+                                            "GitHub".to_string(),
+                                        )
+                                        .build(),
+                                        "github".to_string(),
+                                    )
+                                    .url(format!("https://github.com/{}", repo))
+                                    .build(),
+                                )),
+                                ..Default::default()
+                            }),
+                            None => Block::SectionBlock(SectionBlock {
+                                text: Some(
                                     TextBlockObject::builder(
-                                        TextBlockType::PlainText,
-                                        "GitHub".to_string(),
+                                        TextBlockType::Mrkdwn,
+                                        format!("{} in <#{}>", name, project.slack_channel),
                                     )
                                     .build(),
-                                    "github".to_string(),
-                                )
-                                .url(format!("https://github.com/{}", repo))
-                                .build(),
-                            )),
-                            ..Default::default()
-                        }),
-                        None => Block::SectionBlock(SectionBlock {
-                            text: Some(
-                                TextBlockObject::builder(
-                                    TextBlockType::Mrkdwn,
-                                    format!("{} in <#{}>", name, project.slack_channel),
-                                )
-                                .build(),
-                            ),
-                            ..Default::default()
-                        }),
-                    }
-                })
-                .collect::<Vec<_>>(),
+                                ),
+                                ..Default::default()
+                            }),
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .collect::<Vec<_>>(),
         )
-        .collect::<Vec<_>>(),
-    )
-    .await;
+        .await;
+}
+
+/// Replies with `text` once a change is saved, or links the pull request proposing it.
+pub async fn changed<T>(reply: &dyn ReplySink, change: &Change<T>, text: String) {
+    match &change.status {
+        ChangeStatus::Saved => reply.text(text).await,
+        ChangeStatus::Proposed(url) => {
+            reply
+                .text(format!(
+                    "{}\nThis takes effect once <{}|the config pull request> is merged.",
+                    text, url
+                ))
+                .await
+        }
+        ChangeStatus::Failed(e) => {
+            reply
+                .text(format!(
+                    "Failed to open a pull request for this change: {}",
+                    e
+                ))
+                .await
+        }
+        ChangeStatus::Rejected(e) => {
+            reply
+                .text(format!("This change was not saved:\n{}", e))
+                .await
+        }
+    }
+}
+
+async fn not_a_repo(reply: &dyn ReplySink, repo: &str) {
+    reply
+        .text(format!(
+            "`{}` is not a GitHub repository. Use the `owner/repo` form.",
+            repo
+        ))
+        .await;
 }
 
-pub async fn create<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
+/// The project to change inside `update_manifest`, or the reply when it's gone.
+fn project_mut<'a>(
+    manifest: &'a mut Manifest,
+    project_name: &str,
+) -> Result<&'a mut Project, String> {
+    manifest
+        .projects
+        .get_mut(project_name)
+        .ok_or(format!("Project `{}` does not exist.", project_name))
+}
+
+pub async fn create(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
     project_name: &String,
+    github_repo: Option<String>,
 ) {
-    let mut manifest = crate::config::read_manifest();
+    if let Some(github_repo) = &github_repo {
+        if !is_repo_slug(github_repo) {
+            return not_a_repo(reply, github_repo).await;
+        }
+    }
 
-    if manifest.projects.contains_key(project_name) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!("Project `{}` already exists.", project_name),
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        if manifest.projects.contains_key(project_name) {
+            return Err(format!("Project `{}` already exists.", project_name));
+        }
+
+        if let Some(other) = get_project_name_by_slack_channel(manifest, &ctx.channel_id) {
+            return Err(format!(
+                "This channel is already linked to project `{}`.",
+                other
+            ));
+        }
+
+        manifest.projects.insert(
+            project_name.clone(),
+            Project {
+                slack_channel: ctx.channel_id.clone(),
+                project_owners: vec![],
+                github_repo,
+                ..Default::default()
+            },
         );
-        return;
+
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(change) => {
+            changed(
+                reply,
+                &change,
+                format!("Project `{}` created.", project_name),
+            )
+            .await
+        }
+        Err(message) => reply.text(message).await,
     }
+}
 
-    manifest.projects.insert(
-        project_name.clone(),
-        crate::config::Project {
-            slack_channel: channel_id.clone(),
-            project_owners: vec![],
-            github_repo: None,
-            jira_project: None,
-        },
-    );
+/// Opens the project form for `/ctrl create` without a name, with the GitHub
+/// repository filled in if one was given.
+pub async fn create_form(ctx: &CommandContext, reply: &dyn ReplySink, github_repo: Option<String>) {
+    let trigger_id = match &ctx.trigger_id {
+        Some(trigger_id) => trigger_id,
+        None => {
+            return reply
+                .text("Give the project a name: `/ctrl create <project_name>`.".to_string())
+                .await
+        }
+    };
 
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        format!("Project `{}` created.", project_name),
-    )
+    if let Err(e) = modal::open_create_modal(trigger_id, &ctx.channel_id, github_repo).await {
+        reply
+            .text(format!("Failed to open the project form: {}", e))
+            .await;
+    }
+}
+
+pub async fn edit(ctx: &CommandContext, reply: &dyn ReplySink, project_name: &String) {
+    let manifest = crate::config::read_manifest();
+
+    let (trigger_id, project) = match (&ctx.trigger_id, manifest.projects.get(project_name)) {
+        (Some(trigger_id), Some(project)) => (trigger_id, project),
+        (None, _) => {
+            return reply
+                .text("The project form can't be opened from here.".to_string())
+                .await
+        }
+        (_, None) => {
+            return reply
+                .text(format!("Project `{}` does not exist.", project_name))
+                .await
+        }
+    };
+
+    if let Err(e) = modal::open_edit_modal(trigger_id, &ctx.channel_id, project_name, project).await
+    {
+        reply
+            .text(format!("Failed to open the project form: {}", e))
+            .await;
+    }
+}
+
+pub async fn delete(ctx: &CommandContext, reply: &dyn ReplySink, project_name: &String) {
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        manifest
+            .projects
+            .remove(project_name)
+            .ok_or(format!("Project `{}` does not exist.", project_name))
+    })
     .await;
 
-    crate::config::write_manifest(&manifest);
+    match result {
+        Ok(change) => {
+            changed(
+                reply,
+                &change,
+                format!("Project `{}` deleted.", project_name),
+            )
+            .await
+        }
+        Err(message) => reply.text(message).await,
+    }
 }
 
-pub async fn delete<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
+pub async fn add(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
     project_name: &String,
+    user_id: &String,
 ) {
-    let mut manifest = crate::config::read_manifest();
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        let github_username = match get_user_by_slack_mention(manifest, user_id) {
+            Some(user) => user.github_username.clone(),
+            None => return Ok(false),
+        };
 
-    if !manifest.projects.contains_key(project_name) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!("Project `{}` does not exist.", project_name),
-        );
-        return;
-    }
+        let project = project_mut(manifest, project_name)?;
+
+        if project.project_owners.contains(&github_username) {
+            return Err(format!(
+                "User `{}` is already a manager of `{}`.",
+                user_id, project_name
+            ));
+        }
 
-    manifest.projects.remove(project_name);
+        project.project_owners.push(github_username);
 
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        format!("Project `{}` deleted.", project_name),
-    )
+        Ok(true)
+    })
     .await;
 
-    crate::config::write_manifest(&manifest);
+    match result {
+        Ok(change) if change.value => {
+            changed(
+                reply,
+                &change,
+                format!(
+                    "User `{}` added as a manager of `{}`.",
+                    user_id, project_name
+                ),
+            )
+            .await
+        }
+        Ok(_) => user_not_linked(reply).await,
+        Err(message) => reply.text(message).await,
+    }
 }
 
-pub async fn add<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
+pub async fn remove(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
     project_name: &String,
     user_id: &String,
 ) {
-    let mut manifest = crate::config::read_manifest();
-    let manifest_clone = manifest.clone();
-
-    if !manifest.projects.contains_key(project_name) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!("Project `{}` does not exist.", project_name),
-        );
-        return;
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        let github_username = match get_user_by_slack_mention(manifest, user_id) {
+            Some(user) => user.github_username.clone(),
+            None => return Ok(false),
+        };
+
+        let project = project_mut(manifest, project_name)?;
+
+        if !project.project_owners.contains(&github_username) {
+            return Err(format!(
+                "User `{}` is not a manager of `{}`.",
+                user_id, project_name
+            ));
+        }
+
+        project.project_owners.retain(|x| x != &github_username);
+
+        Ok(true)
+    })
+    .await;
+
+    match result {
+        Ok(change) if change.value => {
+            changed(
+                reply,
+                &change,
+                format!(
+                    "User `{}` removed as a manager of `{}`.",
+                    user_id, project_name
+                ),
+            )
+            .await
+        }
+        Ok(_) => user_not_linked(reply).await,
+        Err(message) => reply.text(message).await,
     }
+}
 
-    let project = manifest.projects.get_mut(project_name).unwrap();
+pub async fn github(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
+    project_name: &String,
+    repo_name: &String,
+) {
+    if !is_repo_slug(repo_name) {
+        return not_a_repo(reply, repo_name).await;
+    }
 
-    let user = get_user_by_slack_mention(&manifest_clone, user_id);
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        project_mut(manifest, project_name)?.github_repo = Some(repo_name.clone());
+        Ok(())
+    })
+    .await;
 
-    if user.is_none() {
-        user_not_linked(socket_mode, channel_id).await;
-        return;
+    match result {
+        Ok(change) => {
+            changed(
+                reply,
+                &change,
+                format!(
+                    "GitHub repository `{}` set for `{}`.",
+                    repo_name, project_name
+                ),
+            )
+            .await
+        }
+        Err(message) => reply.text(message).await,
     }
+}
 
-    let user = user.unwrap();
+pub async fn jira(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
+    project_name: &String,
+    jira_key: &str,
+    statuses: JiraStatuses,
+) {
+    let jira_key = jira_key.to_uppercase();
 
-    if project.project_owners.contains(&user.github_username) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!(
-                "User `{}` is already a manager of `{}`.",
-                user_id, project_name
-            ),
-        );
+    if !crate::jira::is_project_key(&jira_key) {
+        reply
+            .text(format!("`{}` is not a valid Jira project key.", jira_key))
+            .await;
         return;
     }
 
-    project.project_owners.push(user.github_username.clone());
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        let project = project_mut(manifest, project_name)?;
 
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
-        format!(
-            "User `{}` added as a manager of `{}`.",
-            user_id, project_name
-        ),
-    )
+        project.jira_project = Some(jira_key.clone());
+
+        if statuses.opened.is_some() {
+            project.jira_statuses.opened = statuses.opened;
+        }
+        if statuses.approved.is_some() {
+            project.jira_statuses.approved = statuses.approved;
+        }
+        if statuses.merged.is_some() {
+            project.jira_statuses.merged = statuses.merged;
+        }
+
+        Ok(())
+    })
     .await;
 
-    crate::config::write_manifest(&manifest);
+    match result {
+        Ok(change) => {
+            changed(
+                reply,
+                &change,
+                format!("Jira project `{}` set for `{}`.", jira_key, project_name),
+            )
+            .await
+        }
+        Err(message) => reply.text(message).await,
+    }
+}
+
+pub async fn branches_list(reply: &dyn ReplySink, project_name: &String) {
+    let manifest = crate::config::read_manifest();
+
+    let project = match manifest.projects.get(project_name) {
+        Some(project) => project,
+        None => {
+            reply
+                .text(format!("Project `{}` does not exist.", project_name))
+                .await;
+            return;
+        }
+    };
+
+    let mut text = format!("*Branch policy for `{}`*:\n", project_name);
+
+    if project.branch_policy.is_empty() {
+        text.push_str("_Using the default policy._\n");
+    }
+
+    for rule in project.branch_policy() {
+        text.push_str(&format!("• `{}`: {}\n", rule.pattern, rule.describe()));
+    }
+
+    text.push_str("Branches that match no rule are only notified.");
+
+    reply.text(text).await;
 }
 
-pub async fn remove<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
+pub async fn branches_set(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
     project_name: &String,
-    user_id: &String,
+    pattern: &str,
+    mode: &str,
 ) {
-    let mut manifest = crate::config::read_manifest();
-    let manifest_clone = manifest.clone();
-
-    if !manifest.projects.contains_key(project_name) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!("Project `{}` does not exist.", project_name),
-        );
+    let rule = match BranchRule::from_mode(pattern, mode) {
+        Some(rule) => rule,
+        None => {
+            reply
+                .text(format!(
+                    "Unknown mode `{}`. Use `review-merge`, `review`, `merge` or `notify`.",
+                    mode
+                ))
+                .await;
+            return;
+        }
+    };
+
+    if glob::Pattern::new(pattern).is_err() {
+        reply
+            .text(format!("`{}` is not a valid branch pattern.", pattern))
+            .await;
         return;
     }
 
-    let project = manifest.projects.get_mut(project_name).unwrap();
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        let project = project_mut(manifest, project_name)?;
+        let mut policy = project.branch_policy();
+
+        match policy
+            .iter_mut()
+            .find(|existing| existing.pattern == pattern)
+        {
+            Some(existing) => *existing = rule.clone(),
+            None => policy.insert(0, rule.clone()),
+        }
 
-    let user = get_user_by_slack_mention(&manifest_clone, user_id);
+        project.branch_policy = policy;
 
-    if user.is_none() {
-        user_not_linked(socket_mode, channel_id).await;
-        return;
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(change) => {
+            changed(
+                reply,
+                &change,
+                format!(
+                    "PRs into `{}` on `{}` will now use: {}.",
+                    pattern,
+                    project_name,
+                    rule.describe()
+                ),
+            )
+            .await
+        }
+        Err(message) => reply.text(message).await,
     }
+}
 
-    let user = user.unwrap();
+pub async fn branches_remove(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
+    project_name: &String,
+    pattern: &str,
+) {
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        let project = project_mut(manifest, project_name)?;
+        let mut policy = project.branch_policy();
 
-    if !project.project_owners.contains(&user.github_username) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!("User `{}` is not a manager of `{}`.", user_id, project_name),
-        );
-        return;
+        if !policy.iter().any(|rule| rule.pattern == pattern) {
+            return Err(format!("There is no rule for `{}`.", pattern));
+        }
+
+        policy.retain(|rule| rule.pattern != pattern);
+
+        // An empty policy means the default one, so keep a rule that notifies only
+        if policy.is_empty() {
+            policy.push(BranchRule::new("*", false, false));
+        }
+
+        project.branch_policy = policy;
+
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(change) => {
+            changed(
+                reply,
+                &change,
+                format!(
+                    "Removed the rule for `{}` from `{}`.",
+                    pattern, project_name
+                ),
+            )
+            .await
+        }
+        Err(message) => reply.text(message).await,
+    }
+}
+
+pub async fn branches_reset(ctx: &CommandContext, reply: &dyn ReplySink, project_name: &String) {
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        project_mut(manifest, project_name)?.branch_policy.clear();
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(change) => {
+            changed(
+                reply,
+                &change,
+                format!("`{}` now uses the default branch policy.", project_name),
+            )
+            .await
+        }
+        Err(message) => reply.text(message).await,
     }
+}
+
+pub async fn merge(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
+    project_name: &String,
+    method: Option<String>,
+    approvals: Option<String>,
+    owner_approval: Option<String>,
+    checks: Option<String>,
+) {
+    let method = match method {
+        None => None,
+        Some(method) => match MergeMethod::parse(&method) {
+            Some(method) => Some(method),
+            None => {
+                reply
+                    .text(format!(
+                        "Unknown merge method `{}`. Use `merge`, `squash` or `rebase`.",
+                        method
+                    ))
+                    .await;
+                return;
+            }
+        },
+    };
+
+    let approvals = match approvals {
+        None => None,
+        Some(approvals) => match approvals.parse::<usize>() {
+            Ok(approvals) if approvals > 0 => Some(approvals),
+            _ => {
+                reply
+                    .text(format!(
+                        "`{}` is not a valid number of approvals.",
+                        approvals
+                    ))
+                    .await;
+                return;
+            }
+        },
+    };
+
+    let owner_approval = match owner_approval.as_deref() {
+        None => None,
+        Some("yes") => Some(true),
+        Some("no") => Some(false),
+        Some(_) => {
+            reply
+                .text("Use `yes` or `no` for `--owner-approval`.".to_string())
+                .await;
+            return;
+        }
+    };
+
+    let checks = checks.map(|checks| match checks.as_str() {
+        "all" => vec![],
+        _ => checks
+            .split(',')
+            .map(|check| check.trim().to_string())
+            .filter(|check| !check.is_empty())
+            .collect(),
+    });
+
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        let settings = &mut project_mut(manifest, project_name)?.merge;
+
+        if let Some(method) = method {
+            settings.method = method;
+        }
+        if let Some(approvals) = approvals {
+            settings.required_approvals = approvals;
+        }
+        if let Some(owner_approval) = owner_approval {
+            settings.require_owner_approval = owner_approval;
+        }
+        if let Some(checks) = checks {
+            settings.required_checks = checks;
+        }
+
+        Ok(settings.clone())
+    })
+    .await;
 
-    project
-        .project_owners
-        .retain(|x| x != &user.github_username);
+    let change = match result {
+        Ok(change) => change,
+        Err(message) => return reply.text(message).await,
+    };
+    let settings = &change.value;
 
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
+    changed(
+        reply,
+        &change,
         format!(
-            "User `{}` removed as a manager of `{}`.",
-            user_id, project_name
+            "*Merging for `{}`*:\nMethod: `{}`\nApprovals required: {}\nProject owner approval required: {}\nRequired checks: {}",
+            project_name,
+            settings.method.name(),
+            settings.required_approvals,
+            if settings.require_owner_approval {
+                "yes"
+            } else {
+                "no"
+            },
+            match settings.required_checks.is_empty() {
+                true => "all reported checks".to_string(),
+                false => settings
+                    .required_checks
+                    .iter()
+                    .map(|check| format!("`{}`", check))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            }
         ),
     )
     .await;
-
-    crate::config::write_manifest(&manifest);
 }
 
-pub async fn github<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
+pub async fn reviewers(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
     project_name: &String,
-    repo_name: &String,
+    strategy: Option<String>,
+    count: Option<String>,
 ) {
-    let mut manifest = crate::config::read_manifest();
-
-    if !manifest.projects.contains_key(project_name) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!("Project `{}` does not exist.", project_name),
-        );
-        return;
+    let count = match count {
+        None => None,
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Some(count),
+            _ => {
+                reply
+                    .text(format!("`{}` is not a valid number of reviewers.", count))
+                    .await;
+                return;
+            }
+        },
+    };
+
+    if let Some(strategy) = strategy.as_deref() {
+        if !["all", "random", "round-robin", "least-loaded"].contains(&strategy) {
+            reply
+                .text(format!(
+                    "Unknown strategy `{}`. Use `all`, `random`, `round-robin` or `least-loaded`.",
+                    strategy
+                ))
+                .await;
+            return;
+        }
     }
 
-    let project = manifest.projects.get_mut(project_name).unwrap();
-
-    project.github_repo = Some(repo_name.clone());
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        let project = project_mut(manifest, project_name)?;
+
+        // Changing only the count keeps the current strategy, and the reverse
+        let count = count.unwrap_or(match project.reviewers {
+            ReviewerStrategy::All => 1,
+            ReviewerStrategy::Random { count }
+            | ReviewerStrategy::RoundRobin { count }
+            | ReviewerStrategy::LeastLoaded { count } => count,
+        });
+
+        project.reviewers = match strategy.as_deref() {
+            Some("all") => ReviewerStrategy::All,
+            Some("random") => ReviewerStrategy::Random { count },
+            Some("round-robin") => ReviewerStrategy::RoundRobin { count },
+            Some("least-loaded") => ReviewerStrategy::LeastLoaded { count },
+            _ => match project.reviewers {
+                ReviewerStrategy::All => ReviewerStrategy::All,
+                ReviewerStrategy::Random { .. } => ReviewerStrategy::Random { count },
+                ReviewerStrategy::RoundRobin { .. } => ReviewerStrategy::RoundRobin { count },
+                ReviewerStrategy::LeastLoaded { .. } => ReviewerStrategy::LeastLoaded { count },
+            },
+        };
+
+        Ok(project.reviewers.clone())
+    })
+    .await;
 
-    crate::config::write_manifest(&manifest);
+    let change = match result {
+        Ok(change) => change,
+        Err(message) => return reply.text(message).await,
+    };
+
+    let description = match change.value {
+        ReviewerStrategy::All => "all project owners and managers".to_string(),
+        ReviewerStrategy::Random { count } => format!("{} at random", count),
+        ReviewerStrategy::RoundRobin { count } => format!("the next {} in turn", count),
+        ReviewerStrategy::LeastLoaded { count } => {
+            format!("the {} with the fewest open review requests", count)
+        }
+    };
 
-    let _ = respond_text(
-        socket_mode,
-        channel_id,
+    changed(
+        reply,
+        &change,
         format!(
-            "GitHub repository `{}` set for `{}`.",
-            repo_name, project_name
+            "Reviews on `{}` are requested from {}.",
+            project_name, description
         ),
     )
     .await;
 }
 
-pub async fn me<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
-    user_id: &String,
-    subcommand: &str,
-    value: &String,
-) {
-    match subcommand {
-        "github" => {
-            let mut manifest = crate::config::read_manifest();
+pub async fn digest_now(reply: &dyn ReplySink, project_name: &String) {
+    let manifest = crate::config::read_manifest();
 
-            set_user_github_username(&mut manifest, user_id, value);
+    let project = match manifest.projects.get(project_name) {
+        Some(project) => project,
+        None => {
+            reply
+                .text(format!("Project `{}` does not exist.", project_name))
+                .await;
+            return;
+        }
+    };
 
-            let _ = respond_text(
-                socket_mode,
-                channel_id,
-                format!("GitHub username set to `{}`.", value),
-            )
-            .await;
+    let since = crate::scheduler::digest::last_digest(project_name);
+    let digest = crate::scheduler::digest::build_digest(project_name, project, since).await;
+
+    reply.text(digest).await;
+}
+
+pub async fn history(reply: &dyn ReplySink, project_name: Option<String>) {
+    let entries = audit::read_entries()
+        .into_iter()
+        .rev()
+        .filter(|entry| match &project_name {
+            Some(name) => entry
+                .changes
+                .iter()
+                .any(|change| change.target == Target::Project(name.clone())),
+            None => true,
+        })
+        .take(HISTORY_LENGTH)
+        .collect::<Vec<Entry>>();
+
+    if entries.is_empty() {
+        let text = match project_name {
+            Some(name) => format!("No changes to project `{}` have been recorded.", name),
+            None => "No config changes have been recorded.".to_string(),
+        };
+        return reply.text(text).await;
+    }
+
+    reply
+        .text(format!(
+            "*Recent config changes*, newest first. Managers can revert one with `/ctrl rollback <change_id>`.\n\n{}",
+            entries
+                .iter()
+                .map(describe_entry)
+                .collect::<Vec<String>>()
+                .join("\n\n")
+        ))
+        .await;
+}
 
-            crate::config::write_manifest(&manifest);
+fn describe_entry(entry: &Entry) -> String {
+    let author = match entry.slack_id.is_empty() {
+        true => entry.name.clone(),
+        false => format!("<@{}>", entry.slack_id),
+    };
+
+    let mut text = format!(
+        "*#{}* {} by {}",
+        entry.id,
+        entry.time.format("%Y-%m-%d %H:%M UTC"),
+        author
+    );
+    if let Some(command) = &entry.command {
+        text.push_str(&format!(" with `{}`", command));
+    }
+
+    for change in &entry.changes {
+        let what = match (&change.before, &change.after) {
+            (None, _) => "created",
+            (_, None) => "deleted",
+            _ => "changed",
+        };
+
+        let mut lines = audit::changed_lines(change);
+        if lines.len() > HISTORY_LINES {
+            let more = lines.len() - HISTORY_LINES;
+            lines.truncate(HISTORY_LINES);
+            lines.push(format!("… and {} more", more));
         }
-        _ => {
-            command_not_found(socket_mode, channel_id).await;
+
+        text.push_str(&format!(
+            "\n{} {}:\n```{}```",
+            audit::describe(&change.target),
+            what,
+            lines.join("\n")
+        ));
+    }
+
+    text
+}
+
+pub async fn rollback(ctx: &CommandContext, reply: &dyn ReplySink, change_id: &String) {
+    let id = match change_id.trim_start_matches('#').parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => {
+            return reply
+                .text(format!(
+                    "`{}` is not a change ID, see `/ctrl history`.",
+                    change_id
+                ))
+                .await
         }
+    };
+
+    let entry = match audit::read_entries()
+        .into_iter()
+        .find(|entry| entry.id == id)
+    {
+        Some(entry) => entry,
+        None => return reply.text(format!("Change #{} does not exist.", id)).await,
+    };
+
+    let result =
+        try_update_manifest(&ctx.actor(), |manifest| audit::revert(manifest, &entry)).await;
+
+    match result {
+        Ok(change) => changed(reply, &change, format!("Change #{} rolled back.", id)).await,
+        Err(message) => reply.text(message).await,
     }
 }
 
-pub async fn project<S: SlackWebAPIClient>(
-    socket_mode: &SocketMode<S>,
-    channel_id: &String,
-    project_name: &String,
+pub async fn me_github(
+    ctx: &CommandContext,
+    reply: &dyn ReplySink,
+    github_username: &String,
+    user: Option<String>,
 ) {
-    let manifest = crate::config::read_manifest();
+    let slack_id = match &user {
+        Some(user) if user.starts_with("<@") => user
+            .trim_start_matches("<@")
+            .trim_end_matches('>')
+            .split('|')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        Some(user) => {
+            return reply
+                .text(format!(
+                    "`{}` is not a Slack user. Mention them with `@`.",
+                    user
+                ))
+                .await
+        }
+        None => ctx.user_id.clone(),
+    };
+
+    // Roles come from the GitHub username, so it can only be linked to one person,
+    // and only a manager can hand out the roles of managers and project owners
+    let result = try_update_manifest(&ctx.actor(), |manifest| {
+        match get_slack_by_github_username(manifest, github_username) {
+            Some(linked) if linked == slack_id => return Ok(()),
+            Some(linked) => {
+                return Err(format!(
+                    "`{}` is already linked to <@{}>.",
+                    github_username, linked
+                ))
+            }
+            None => (),
+        }
 
-    if !manifest.projects.contains_key(project_name) {
-        let _ = respond_text(
-            socket_mode,
-            channel_id,
-            format!("Project `{}` does not exist.", project_name),
-        );
-        return;
+        let privileged = manifest.managers.contains(github_username)
+            || manifest
+                .projects
+                .values()
+                .any(|project| project.project_owners.contains(github_username));
+        if privileged && ctx.role < Role::Manager {
+            return Err(format!(
+                "`{0}` belongs to a manager or project owner, so only a manager can link it, with `/ctrl me github {0} --for @you`.",
+                github_username
+            ));
+        }
+
+        set_user_github_username(manifest, &slack_id, github_username);
+        Ok(())
+    })
+    .await;
+
+    let text = match user {
+        Some(_) => format!(
+            "GitHub username of <@{}> set to `{}`.",
+            slack_id, github_username
+        ),
+        None => format!("GitHub username set to `{}`.", github_username),
+    };
+
+    match result {
+        Ok(change) => changed(reply, &change, text).await,
+        Err(message) => reply.text(message).await,
     }
+}
+
+pub async fn me_jira(ctx: &CommandContext, reply: &dyn ReplySink, account_id: &String) {
+    let change = update_manifest(&ctx.actor(), |manifest| {
+        set_user_jira_account_id(manifest, &ctx.user_id, account_id);
+    })
+    .await;
 
-    let project = manifest.projects.get(project_name).unwrap();
+    changed(
+        reply,
+        &change,
+        format!("Jira account set to `{}`.", account_id),
+    )
+    .await;
+}
+
+pub async fn project(reply: &dyn ReplySink, project_name: &String) {
+    let manifest = crate::config::read_manifest();
+
+    let project = match manifest.projects.get(project_name) {
+        Some(project) => project,
+        None => {
+            reply
+                .text(format!("Project `{}` does not exist.", project_name))
+                .await;
+            return;
+        }
+    };
 
     let mut text = format!("*Project*: `{}`\n", project_name);
 
@@ -483,24 +1140,32 @@ pub async fn project<S: SlackWebAPIClient>(
         ));
     }
 
+    if let Some(ref jira_project) = project.jira_project {
+        text.push_str(&format!("*Jira*: `{}`\n", jira_project));
+
+        let statuses = [
+            ("opened", &project.jira_statuses.opened),
+            ("approved", &project.jira_statuses.approved),
+            ("merged", &project.jira_statuses.merged),
+        ];
+
+        for (stage, status) in statuses {
+            if let Some(status) = status {
+                text.push_str(&format!("    When {}: _{}_\n", stage, status));
+            }
+        }
+    }
+
     text.push_str("*Managers*:\n");
 
     for manager in &project.project_owners {
         let slack_id = get_slack_by_github_username(&manifest, manager);
         let user = get_user_by_github_username(&manifest, manager);
 
-        if user.is_none() || slack_id.is_none() {
-            continue;
+        if let (Some(slack_id), Some(user)) = (slack_id, user) {
+            text.push_str(&format!("<@{}> ({})\n", slack_id, user.github_username));
         }
-
-        let user = user.unwrap();
-
-        text.push_str(&format!(
-            "<@{}> ({})\n",
-            slack_id.unwrap(),
-            user.github_username
-        ));
     }
 
-    let _ = respond_text(socket_mode, channel_id, text).await;
+    reply.text(text).await;
 }
@@ -1,16 +1,26 @@
-use slack_rust;
-use slack_rust::chat::post_message::{post_message, PostMessageRequest};
 use slack_rust::http_client::{default_client, SlackWebAPIClient};
+use slack_rust::payloads::interactive::{InteractiveEventType, InteractivePayload, SlashPayload};
 use slack_rust::socket::event::{HelloEvent, InteractiveEvent, SlashCommandsEvent};
 use slack_rust::socket::socket_mode::{ack, EventHandler, SocketMode, Stream};
-use slack_rust::views::open::{open, OpenRequest};
-use slack_rust::views::view::{View, ViewType};
 use std::env;
-use std::sync::Mutex;
 
-use crate::config::{get_project_by_slack_channel, get_project_name_by_slack_channel};
+use ::rocket::serde::json::{json, Value};
+use async_tungstenite::tungstenite::Message;
+use futures_util::SinkExt;
 
+use crate::error::{self, Error};
+use crate::Parameters;
+use command::CommandContext;
+use reply::{ChannelSink, ResponseUrlSink};
+
+pub mod command;
 pub mod handler;
+pub mod modal;
+pub mod parser;
+pub mod permissions;
+pub mod pull_request;
+pub mod reply;
+pub mod rocket;
 
 pub async fn start() {
     let slack_app_token = env::var("SLACK_APP_TOKEN").expect("slack app token is not set.");
@@ -35,118 +45,155 @@ where
         println!("hello event: {:?}", e);
     }
 
+    async fn on_interactive(
+        &mut self,
+        socket_mode: &SocketMode<S>,
+        e: InteractiveEvent,
+        s: &mut Stream,
+    ) {
+        let handled = match interaction_response(&e.payload) {
+            Ok(Some(response)) => ack_with_payload(&e.envelope_id, response, s).await,
+            Ok(None) => match ack(&e.envelope_id, s).await {
+                Ok(()) => handle_interaction(e.payload).await,
+                Err(e) => Err(Error::from(e)),
+            },
+            Err(error) => {
+                let _ = ack(&e.envelope_id, s).await;
+                Err(error)
+            }
+        };
+
+        if let Err(e) = handled {
+            error::report(&e, "Handling a Slack interaction", None).await;
+        }
+    }
+
     async fn on_slash_commands(
         &mut self,
         socket_mode: &SocketMode<S>,
         e: SlashCommandsEvent,
         s: &mut Stream,
     ) {
-        let payload = e.payload;
+        let channel_id = e.payload.channel_id.clone();
 
-        ack(&e.envelope_id, s)
-            .await
-            .expect("socket mode ack error.");
+        let handled = match ack(&e.envelope_id, s).await {
+            Ok(()) => run_slash_command(e.payload).await,
+            Err(e) => Err(Error::from(e)),
+        };
 
-        let text = payload.text.expect("Text missing");
-        let opts = text.split_whitespace().collect::<Vec<&str>>();
+        if let Err(e) = handled {
+            error::report(&e, "Handling a Slack command", channel_id.as_deref()).await;
+        }
+    }
+}
 
-        let channel_id = payload.channel_id.expect("Channel ID missing");
+/// Runs a slash command received over Socket Mode, replying in its channel.
+async fn run_slash_command(payload: SlashPayload) -> error::Result<()> {
+    let missing = |field: &str| Error::Payload(format!("the command has no {}", field));
+
+    let channel_id = payload.channel_id.ok_or_else(|| missing("channel_id"))?;
+    let ctx = CommandContext::new(
+        payload.user_id.ok_or_else(|| missing("user_id"))?,
+        payload.user_name.unwrap_or_default(),
+        channel_id.clone(),
+        payload.text.unwrap_or_default(),
+        payload.trigger_id,
+    );
+    let reply = ChannelSink { channel_id };
+
+    command::run(&ctx, &reply).await;
+    Ok(())
+}
 
-        if opts.len() < 1 {
-            handler::not_enough_arguments(socket_mode, &channel_id).await;
-            return;
+/// Handles a button press or modal submission, received over Socket Mode or HTTP.
+pub async fn handle_interaction(payload: InteractivePayload) -> error::Result<()> {
+    let callback_id = payload
+        .view
+        .as_ref()
+        .and_then(|view| view.callback_id.clone());
+    let block_id = payload
+        .actions
+        .as_ref()
+        .and_then(|actions| actions.first())
+        .and_then(|action| action.block_id.clone());
+
+    match (&payload.type_filed, callback_id.as_deref(), block_id.as_deref()) {
+        (InteractiveEventType::ViewSubmission, Some(modal::PROJECT_MODAL), _) => {
+            modal::submit(payload).await
+        }
+        (InteractiveEventType::BlockActions, _, Some(pull_request::ACTIONS_BLOCK)) => {
+            pull_request::handle_action(payload).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Handles a slash command received over HTTP, replying through its `response_url`.
+pub fn http_handler(parameters: Parameters) {
+    println!("Received Slack command: {:?}", parameters.text);
+
+    ::rocket::tokio::spawn(async move {
+        let ctx = CommandContext::new(
+            parameters.user_id,
+            parameters.user_name,
+            parameters.channel_id,
+            parameters.text,
+            Some(parameters.trigger_id),
+        );
+        let reply = ResponseUrlSink {
+            response_url: parameters.response_url,
         };
 
-        let manifest = crate::config::read_manifest();
-        let project = get_project_name_by_slack_channel(&manifest, &channel_id);
-
-        let (command, args) = &opts.split_at(1);
-        let command = command[0];
-
-        match command {
-            "help" => handler::help(socket_mode, &channel_id).await,
-            "list" => handler::list(socket_mode, &channel_id).await,
-            "create" => handler::create(socket_mode, &channel_id, &args[0].to_string()).await,
-            "delete" => handler::delete(socket_mode, &channel_id, &args[0].to_string()).await,
-            "add" => {
-                if project.is_none() {
-                    handler::project_not_found(socket_mode, &channel_id).await;
-                    return;
-                }
-        
-                let project = project.unwrap();
-
-                handler::add(
-                    socket_mode,
-                    &channel_id,
-                    &project.to_string(),
-                    &args[0].to_string(),
-                )
-                .await
-            }
-            "remove" => {
-                if project.is_none() {
-                    handler::project_not_found(socket_mode, &channel_id).await;
-                    return;
-                }
-
-                let project = project.unwrap();
-                
-                handler::remove(
-                    socket_mode,
-                    &channel_id,
-                    &project.to_string(),
-                    &args[0].to_string(),
-                )
-                .await
-            }
-            "github" => {
-                if project.is_none() {
-                    handler::project_not_found(socket_mode, &channel_id).await;
-                    return;
-                }
-
-                let project = project.unwrap();
-                
-                handler::github(
-                    socket_mode,
-                    &channel_id,
-                    &project.to_string(),
-                    &args[0].to_string(),
-                )
-                .await
-            },
-            "me" => {
-                if args.len() < 2 {
-                    handler::not_enough_arguments(socket_mode, &channel_id).await;
-                    return;
-                }
-
-                handler::me(
-                    socket_mode,
-                    &channel_id,
-                    &payload.user_id.expect("User ID missing"),
-                    &args[0].to_string(),
-                    &args[1].to_string(),
-                )
-                .await
-            },
-            "project" => {
-                if project.is_none() {
-                    handler::project_not_found(socket_mode, &channel_id).await;
-                    return;
-                }
-
-                let project = project.unwrap();
-                
-                handler::project(
-                    socket_mode,
-                    &channel_id,
-                    &project.to_string(),
-                )
-                .await
-            },
-            _ => handler::command_not_found(socket_mode, &channel_id).await,
+        command::run(&ctx, &reply).await;
+    });
+}
+
+/// Handles an interaction received over HTTP, returning the body to answer Slack
+/// with. Anything else is handled in the background after Slack has been answered.
+pub fn http_interaction_handler(payload: InteractivePayload) -> Option<Value> {
+    let failed = match interaction_response(&payload) {
+        Ok(Some(response)) => return Some(response),
+        Ok(None) => None,
+        Err(e) => Some(e),
+    };
+
+    ::rocket::tokio::spawn(async move {
+        let handled = match failed {
+            Some(e) => Err(e),
+            None => handle_interaction(payload).await,
+        };
+
+        if let Err(e) = handled {
+            error::report(&e, "Handling a Slack interaction", None).await;
+        }
+    });
+
+    None
+}
+
+/// What to answer an interaction with before handling it. Project forms with
+/// mistakes are answered with the errors to show under their fields, which keeps
+/// the form open, and are not handled further.
+pub fn interaction_response(payload: &InteractivePayload) -> error::Result<Option<Value>> {
+    let callback_id = payload
+        .view
+        .as_ref()
+        .and_then(|view| view.callback_id.as_deref());
+
+    match (&payload.type_filed, callback_id) {
+        (InteractiveEventType::ViewSubmission, Some(modal::PROJECT_MODAL)) => {
+            modal::check(payload)
         }
+        _ => Ok(None),
     }
 }
+
+/// Acknowledges a Socket Mode envelope with a response payload, which slack_rust's
+/// `ack` can't send.
+async fn ack_with_payload(envelope_id: &str, payload: Value, s: &mut Stream) -> error::Result<()> {
+    let message = json!({ "envelope_id": envelope_id, "payload": payload });
+
+    s.send(Message::Text(message.to_string()))
+        .await
+        .map_err(|e| Error::Slack(e.to_string()))
+}
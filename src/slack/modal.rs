@@ -0,0 +1,764 @@
+use std::env;
+
+use rocket::serde::json::{json, serde_json, Value};
+use serde::{Deserialize, Serialize};
+use slack_rust::{
+    block::{
+        block_elements::{
+            BlockElement, CheckboxGroupsBlockElement, MultiSelectBlockElement,
+            PlainTextInputBlockElement, SelectBlockElement,
+        },
+        block_input::InputBlock,
+        block_object::{OptionBlockObject, TextBlockObject, TextBlockType},
+        block_section::SectionBlock,
+        blocks::Block,
+    },
+    http_client::{default_client, get_slack_url, SlackWebAPIClient},
+    payloads::interactive::InteractivePayload,
+    views::{
+        open::{OpenRequest, OpenResponse},
+        view::{View, ViewState, ViewType},
+    },
+};
+
+use super::{
+    handler::{changed, respond_ephemeral},
+    permissions::Role,
+    reply::ChannelSink,
+};
+use crate::{
+    config::{
+        branches::BranchRule,
+        get_project_name_by_slack_channel, read_manifest, try_update_manifest,
+        validate::{is_repo_slug, validate, Severity},
+        Actor, Manifest, MergeMethod, MergeSettings, Project,
+    },
+    error::{self, Error},
+};
+
+/// The `callback_id` of the project modal, to recognise its submissions.
+pub const PROJECT_MODAL: &str = "project";
+
+/// Slack shows at most this many options in a select.
+const MAX_OPTIONS: usize = 100;
+
+/// Where the modal was opened and which project it edits, kept in the view's
+/// `private_metadata` until it is submitted.
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    channel_id: String,
+    /// The project being edited, `None` when creating one.
+    project: Option<String>,
+}
+
+impl Metadata {
+    /// The `block_id` of the first input, for problems that don't belong to one.
+    fn first_input(&self) -> &'static str {
+        match self.project {
+            Some(_) => "github_repo",
+            None => "name",
+        }
+    }
+}
+
+/// A problem with the form, shown under the input with this `block_id`.
+type FieldError = (&'static str, String);
+
+/// The values of the modal's fields, from a project or from a submission.
+struct ProjectForm {
+    name: String,
+    github_repo: String,
+    jira_project: String,
+    /// GitHub usernames.
+    owners: Vec<String>,
+    /// One `<pattern> <mode>` rule per line.
+    branch_policy: String,
+    merge_method: MergeMethod,
+    approvals: String,
+    owner_approval: bool,
+    /// Comma-separated check names.
+    checks: String,
+}
+
+impl ProjectForm {
+    fn new(github_repo: Option<String>) -> ProjectForm {
+        ProjectForm {
+            github_repo: github_repo.unwrap_or_default(),
+            ..ProjectForm::from_project("", &Project::default())
+        }
+    }
+
+    fn from_project(name: &str, project: &Project) -> ProjectForm {
+        ProjectForm {
+            name: name.to_string(),
+            github_repo: project.github_repo.clone().unwrap_or_default(),
+            jira_project: project.jira_project.clone().unwrap_or_default(),
+            owners: project.project_owners.clone(),
+            branch_policy: project
+                .branch_policy
+                .iter()
+                .map(|rule| format!("{} {}", rule.pattern, rule.mode()))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            merge_method: project.merge.method,
+            approvals: project.merge.required_approvals.to_string(),
+            owner_approval: project.merge.require_owner_approval,
+            checks: project.merge.required_checks.join(", "),
+        }
+    }
+
+    fn from_state(state: &ViewState) -> ProjectForm {
+        let action = |block: &str| state.values.get(block).and_then(|block| block.get("value"));
+        let text = |block: &str| {
+            action(block)
+                .and_then(|action| action.value.clone())
+                .unwrap_or_default()
+        };
+        let selected = |block: &str| {
+            action(block)
+                .and_then(|action| action.selected_options.as_ref())
+                .into_iter()
+                .flatten()
+                .filter_map(|option| option.value.clone())
+                .collect::<Vec<String>>()
+        };
+
+        ProjectForm {
+            name: text("name"),
+            github_repo: text("github_repo"),
+            jira_project: text("jira_project"),
+            owners: selected("owners"),
+            branch_policy: text("branch_policy"),
+            merge_method: action("merge_method")
+                .and_then(|action| action.selected_option.as_ref())
+                .and_then(|option| option.value.as_deref())
+                .and_then(MergeMethod::parse)
+                .unwrap_or_default(),
+            approvals: text("approvals"),
+            owner_approval: !selected("owner_approval").is_empty(),
+            checks: text("checks"),
+        }
+    }
+
+    /// Checks the form and writes it to the project, creating it if needed. Returns
+    /// the project's name, or what to fix.
+    fn apply(
+        &self,
+        manifest: &mut Manifest,
+        metadata: &Metadata,
+    ) -> Result<String, Vec<FieldError>> {
+        let mut errors = vec![];
+
+        let name = match &metadata.project {
+            Some(name) => name.clone(),
+            None => self.name.trim().to_string(),
+        };
+
+        if metadata.project.is_none() {
+            if name.is_empty() {
+                errors.push(("name", "Give the project a name.".to_string()));
+            } else if manifest.projects.contains_key(&name) {
+                errors.push(("name", format!("Project `{}` already exists.", name)));
+            }
+
+            if let Some(other) = get_project_name_by_slack_channel(manifest, &metadata.channel_id) {
+                errors.push((
+                    "name",
+                    format!("This channel is already linked to project `{}`.", other),
+                ));
+            }
+        }
+
+        let github_repo = optional(&self.github_repo);
+        if let Some(repo) = &github_repo {
+            if !is_repo_slug(repo) {
+                errors.push((
+                    "github_repo",
+                    format!(
+                        "`{}` is not a GitHub repository, use the `owner/repo` form.",
+                        repo
+                    ),
+                ));
+            }
+        }
+
+        let jira_project = optional(&self.jira_project).map(|key| key.to_uppercase());
+        if let Some(key) = &jira_project {
+            if !crate::jira::is_project_key(key) {
+                errors.push((
+                    "jira_project",
+                    format!("`{}` is not a valid Jira project key.", key),
+                ));
+            }
+        }
+
+        let approvals = match self.approvals.trim().parse::<usize>() {
+            Ok(approvals) if approvals > 0 => approvals,
+            _ => {
+                errors.push((
+                    "approvals",
+                    format!(
+                        "`{}` is not a valid number of approvals.",
+                        self.approvals.trim()
+                    ),
+                ));
+                MergeSettings::default().required_approvals
+            }
+        };
+
+        let mut branch_policy = vec![];
+        for line in self.branch_policy.lines().map(str::trim) {
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            match words.as_slice() {
+                [] => (),
+                [pattern, mode] if glob::Pattern::new(pattern).is_ok() => {
+                    match BranchRule::from_mode(pattern, mode) {
+                        Some(rule) => branch_policy.push(rule),
+                        None => errors.push((
+                            "branch_policy",
+                            format!(
+                                "Unknown mode `{}`. Use `review-merge`, `review`, `merge` or `notify`.",
+                                mode
+                            ),
+                        )),
+                    }
+                }
+                [pattern, _] => errors.push((
+                    "branch_policy",
+                    format!("`{}` is not a valid branch pattern.", pattern),
+                )),
+                _ => errors.push((
+                    "branch_policy",
+                    format!(
+                        "`{}` should be a branch pattern and a mode, e.g. `release/* review`.",
+                        line
+                    ),
+                )),
+            }
+        }
+
+        let checks = match self.checks.trim() {
+            "all" => vec![],
+            checks => checks
+                .split(',')
+                .map(|check| check.trim().to_string())
+                .filter(|check| !check.is_empty())
+                .collect(),
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let project = match &metadata.project {
+            Some(name) => manifest.projects.get_mut(name).ok_or(vec![(
+                metadata.first_input(),
+                format!("Project `{}` does not exist.", name),
+            )])?,
+            None => manifest.projects.entry(name.clone()).or_insert(Project {
+                slack_channel: metadata.channel_id.clone(),
+                ..Default::default()
+            }),
+        };
+
+        project.github_repo = github_repo;
+        project.jira_project = jira_project;
+        project.project_owners = self.owners.clone();
+        project.branch_policy = branch_policy;
+        project.merge.method = self.merge_method;
+        project.merge.required_approvals = approvals;
+        project.merge.require_owner_approval = self.owner_approval;
+        project.merge.required_checks = checks;
+
+        // Catch anything else the manifest wouldn't load with
+        let prefix = format!("projects.{}.", name);
+        let errors = validate(manifest)
+            .into_iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .filter_map(|problem| {
+                let field = problem.path.strip_prefix(&prefix)?;
+                let block_id = match field.split(['.', '[']).next() {
+                    Some("github_repo") => "github_repo",
+                    Some("jira_project") => "jira_project",
+                    Some("project_owners") => "owners",
+                    Some("branch_policy") => "branch_policy",
+                    Some("merge") if field.contains("approvals") => "approvals",
+                    Some("merge") if field.contains("checks") => "checks",
+                    _ => metadata.first_input(),
+                };
+                Some((block_id, problem.message))
+            })
+            .collect::<Vec<FieldError>>();
+
+        match errors.is_empty() {
+            true => Ok(name),
+            false => Err(errors),
+        }
+    }
+
+    fn view(&self, manifest: &Manifest, metadata: &Metadata) -> View {
+        let mut blocks = vec![];
+
+        match &metadata.project {
+            Some(name) => blocks.push(section(format!("*Project*: `{}`", name))),
+            None => blocks.push(input(
+                "name",
+                "Project name",
+                text_input(&self.name, "my-project", false),
+                None,
+                false,
+            )),
+        }
+
+        blocks.push(input(
+            "github_repo",
+            "GitHub repository",
+            text_input(&self.github_repo, "owner/repo", false),
+            None,
+            true,
+        ));
+        blocks.push(input(
+            "jira_project",
+            "Jira project key",
+            text_input(&self.jira_project, "CTRL", false),
+            None,
+            true,
+        ));
+
+        let owners = owner_options(manifest, &self.owners);
+        match owners.is_empty() {
+            true => blocks.push(section(
+                "No one can be made an owner until they link their GitHub account with `/ctrl me github <github_username>`.".to_string(),
+            )),
+            false => blocks.push(input(
+                "owners",
+                "Owners",
+                BlockElement::MultiSelectBlockElement(MultiSelectBlockElement {
+                    placeholder: plain_text("Pick the project owners"),
+                    action_id: "value".to_string(),
+                    options: owners.iter().map(|owner| option(owner, owner)).collect(),
+                    ..Default::default()
+                }),
+                Some("Owners can edit the project and are asked to review its PRs."),
+                true,
+            )),
+        }
+
+        blocks.push(input(
+            "branch_policy",
+            "Branch policy",
+            text_input(
+                &self.branch_policy,
+                "main review\nrelease/* notify\n* review-merge",
+                true,
+            ),
+            Some("One rule per line, checked in order: a branch pattern and `review-merge`, `review`, `merge` or `notify`. Leave empty for the default policy."),
+            true,
+        ));
+
+        let methods = [MergeMethod::Merge, MergeMethod::Squash, MergeMethod::Rebase];
+        blocks.push(input(
+            "merge_method",
+            "Merge method",
+            BlockElement::SelectBlockElement(SelectBlockElement {
+                placeholder: plain_text("Pick a merge method"),
+                action_id: "value".to_string(),
+                options: methods
+                    .iter()
+                    .map(|method| option(method.name(), method.name()))
+                    .collect(),
+                initial_option: Some(option(self.merge_method.name(), self.merge_method.name())),
+                ..Default::default()
+            }),
+            None,
+            false,
+        ));
+        blocks.push(input(
+            "approvals",
+            "Required approvals",
+            text_input(&self.approvals, "1", false),
+            None,
+            false,
+        ));
+
+        let owner_approval = || option("Require an approval from a project owner", "yes");
+        blocks.push(input(
+            "owner_approval",
+            "Owner approval",
+            BlockElement::CheckboxGroupsBlockElement(CheckboxGroupsBlockElement {
+                action_id: "value".to_string(),
+                options: vec![owner_approval()],
+                initial_options: match self.owner_approval {
+                    true => Some(vec![owner_approval()]),
+                    false => None,
+                },
+                ..Default::default()
+            }),
+            None,
+            true,
+        ));
+        blocks.push(input(
+            "checks",
+            "Required checks",
+            text_input(&self.checks, "build, test", false),
+            Some("Comma-separated check names. Leave empty to require every reported check."),
+            true,
+        ));
+
+        View {
+            type_filed: Some(ViewType::Modal),
+            callback_id: Some(PROJECT_MODAL.to_string()),
+            private_metadata: serde_json::to_string(metadata).ok(),
+            title: Some(plain_text(match metadata.project {
+                Some(_) => "Edit project",
+                None => "New project",
+            })),
+            submit: Some(plain_text("Save")),
+            close: Some(plain_text("Cancel")),
+            blocks: Some(blocks),
+            ..Default::default()
+        }
+    }
+}
+
+/// The usernames offered in the owners select. Owners are GitHub usernames, so only
+/// people with a linked profile can be picked. The current owners come first, so
+/// they stay selected even without a profile or past Slack's option limit, and
+/// submitting the form doesn't remove them.
+fn owner_options(manifest: &Manifest, current: &[String]) -> Vec<String> {
+    let mut linked = manifest
+        .profiles
+        .values()
+        .map(|profile| profile.github_username.clone())
+        .filter(|github_username| !github_username.is_empty() && !current.contains(github_username))
+        .collect::<Vec<String>>();
+    linked.sort();
+    linked.dedup();
+
+    let mut owners = current.to_vec();
+    owners.extend(linked);
+    owners.truncate(MAX_OPTIONS);
+    owners
+}
+
+fn optional(value: &str) -> Option<String> {
+    match value.trim() {
+        "" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+fn plain_text(text: &str) -> TextBlockObject {
+    TextBlockObject::builder(TextBlockType::PlainText, text.to_string()).build()
+}
+
+fn option(text: &str, value: &str) -> OptionBlockObject {
+    OptionBlockObject {
+        text: plain_text(text),
+        value: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
+fn section(text: String) -> Block {
+    Block::SectionBlock(SectionBlock {
+        text: Some(TextBlockObject::builder(TextBlockType::Mrkdwn, text).build()),
+        ..Default::default()
+    })
+}
+
+fn text_input(value: &str, placeholder: &str, multiline: bool) -> BlockElement {
+    BlockElement::PlainTextInputBlockElement(PlainTextInputBlockElement {
+        action_id: "value".to_string(),
+        placeholder: Some(plain_text(placeholder)),
+        initial_value: optional(value).map(|_| value.to_string()),
+        multiline: Some(multiline),
+        ..Default::default()
+    })
+}
+
+/// An input block whose element has the action ID `value`, found under `block_id`
+/// in the submitted state.
+fn input(
+    block_id: &str,
+    label: &str,
+    element: BlockElement,
+    hint: Option<&str>,
+    optional: bool,
+) -> Block {
+    Block::InputBlock(InputBlock {
+        label: plain_text(label),
+        element,
+        block_id: Some(block_id.to_string()),
+        hint: hint.map(plain_text),
+        optional: Some(optional),
+        ..Default::default()
+    })
+}
+
+/// Opens the project modal for creating a project in `channel_id`.
+pub async fn open_create_modal(
+    trigger_id: &str,
+    channel_id: &str,
+    github_repo: Option<String>,
+) -> error::Result<()> {
+    let metadata = Metadata {
+        channel_id: channel_id.to_string(),
+        project: None,
+    };
+
+    open(trigger_id, &ProjectForm::new(github_repo), &metadata).await
+}
+
+/// Opens the project modal filled in with the settings of `project`.
+pub async fn open_edit_modal(
+    trigger_id: &str,
+    channel_id: &str,
+    project_name: &str,
+    project: &Project,
+) -> error::Result<()> {
+    let metadata = Metadata {
+        channel_id: channel_id.to_string(),
+        project: Some(project_name.to_string()),
+    };
+
+    open(
+        trigger_id,
+        &ProjectForm::from_project(project_name, project),
+        &metadata,
+    )
+    .await
+}
+
+async fn open(trigger_id: &str, form: &ProjectForm, metadata: &Metadata) -> error::Result<()> {
+    let slack_bot_token = env::var("SLACK_BOT_TOKEN")
+        .map_err(|_| Error::Slack("SLACK_BOT_TOKEN is not set".to_string()))?;
+
+    let request = OpenRequest {
+        trigger_id: trigger_id.to_string(),
+        view: form.view(&read_manifest(), metadata),
+    };
+
+    // slack_rust's multi select has no `initial_options`, so the current owners are
+    // selected in the JSON instead
+    let mut request = serde_json::to_value(&request).map_err(slack_rust::error::Error::from)?;
+    let blocks = request
+        .pointer_mut("/view/blocks")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten();
+    for block in blocks.filter(|block| block["block_id"] == "owners") {
+        if !form.owners.is_empty() {
+            block["element"]["initial_options"] = json!(form
+                .owners
+                .iter()
+                .map(|owner| option(owner, owner))
+                .collect::<Vec<OptionBlockObject>>());
+        }
+    }
+
+    let response = default_client()
+        .post_json(
+            &get_slack_url("views.open"),
+            &request.to_string(),
+            &slack_bot_token,
+        )
+        .await?;
+    let response =
+        serde_json::from_str::<OpenResponse>(&response).map_err(slack_rust::error::Error::from)?;
+
+    match response.ok {
+        true => Ok(()),
+        false => Err(Error::Slack(format!(
+            "views.open failed: {}",
+            response.error.unwrap_or_default()
+        ))),
+    }
+}
+
+/// A submission of the project modal.
+struct Submission {
+    metadata: Metadata,
+    form: ProjectForm,
+    user_id: String,
+    user_name: String,
+}
+
+impl Submission {
+    fn parse(payload: &InteractivePayload) -> error::Result<Submission> {
+        let view = payload
+            .view
+            .as_ref()
+            .ok_or(Error::Payload("the submission has no view".to_string()))?;
+        let metadata =
+            serde_json::from_str::<Metadata>(view.private_metadata.as_deref().unwrap_or(""))
+                .map_err(|e| Error::Payload(format!("unreadable private_metadata: {}", e)))?;
+        let user = payload
+            .user
+            .as_ref()
+            .ok_or(Error::Payload("the submission has no user".to_string()))?;
+        let user_id = user
+            .id
+            .clone()
+            .ok_or(Error::Payload("the submission has no user ID".to_string()))?;
+
+        let form = match &view.state {
+            Some(state) => ProjectForm::from_state(state),
+            None => return Err(Error::Payload("the submission has no state".to_string())),
+        };
+
+        Ok(Submission {
+            metadata,
+            form,
+            user_id,
+            user_name: user.name.clone().unwrap_or_default(),
+        })
+    }
+
+    /// The role needed to save the form, and the command it stands in for.
+    fn required(&self) -> (Role, &'static str) {
+        match &self.metadata.project {
+            Some(_) => (Role::Owner, "/ctrl edit"),
+            None => (Role::Manager, "/ctrl create"),
+        }
+    }
+
+    /// Checks the form against `manifest` and applies it, returning what to fix.
+    fn apply(&self, manifest: &mut Manifest) -> Result<String, Vec<FieldError>> {
+        let (required, _) = self.required();
+        let role = Role::of(manifest, &self.user_id, self.metadata.project.as_deref());
+        if role < required {
+            return Err(vec![(
+                self.metadata.first_input(),
+                format!(
+                    "You need to be a {} to save this.",
+                    match required {
+                        Role::Manager => "manager",
+                        _ => "project owner or manager",
+                    }
+                ),
+            )]);
+        }
+
+        self.form.apply(manifest, &self.metadata)
+    }
+}
+
+/// Checks a submitted project modal before Slack is answered. Returns the
+/// `response_action` that keeps the modal open and shows what to fix under each
+/// field, or `None` to close it and save it with `submit`.
+pub fn check(payload: &InteractivePayload) -> error::Result<Option<Value>> {
+    let submission = Submission::parse(payload)?;
+
+    let errors = match submission.apply(&mut read_manifest()) {
+        Ok(_) => return Ok(None),
+        Err(errors) => errors,
+    };
+
+    let mut by_block = serde_json::Map::new();
+    for (block_id, message) in errors {
+        match by_block.get_mut(block_id) {
+            Some(Value::String(messages)) => {
+                messages.push(' ');
+                messages.push_str(&message);
+            }
+            _ => {
+                by_block.insert(block_id.to_string(), Value::String(message));
+            }
+        }
+    }
+
+    Ok(Some(json!({
+        "response_action": "errors",
+        "errors": by_block,
+    })))
+}
+
+/// Saves a submitted project modal once `check` has passed it. If the manifest
+/// changed in the meantime so that it no longer applies, the user is told why.
+pub async fn submit(payload: InteractivePayload) -> error::Result<()> {
+    let submission = Submission::parse(&payload)?;
+    let metadata = &submission.metadata;
+
+    let (_, command) = submission.required();
+    let actor = Actor {
+        command: Some(command.to_string()),
+        ..Actor::new(&submission.user_id, &submission.user_name)
+    };
+
+    match try_update_manifest(&actor, |manifest| submission.apply(manifest)).await {
+        Ok(change) => {
+            let text = format!(
+                "Project `{}` {} by <@{}>.",
+                change.value,
+                match metadata.project {
+                    Some(_) => "updated",
+                    None => "created",
+                },
+                submission.user_id
+            );
+            let reply = ChannelSink {
+                channel_id: metadata.channel_id.clone(),
+            };
+
+            changed(&reply, &change, text).await;
+            Ok(())
+        }
+        Err(errors) => {
+            respond_ephemeral(
+                &metadata.channel_id,
+                &submission.user_id,
+                format!(
+                    "The project was not saved:\n{}",
+                    errors
+                        .iter()
+                        .map(|(_, message)| format!("⚠️ {}", message))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ),
+            )
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::schema;
+
+    #[test]
+    fn keeps_owners_without_a_profile() {
+        let mut manifest = schema::parse(
+            r#"
+            version = 1
+            managers = []
+            configured_project = "ctrl"
+
+            [projects.ctrl]
+            slack_channel = "C1"
+            project_owners = ["zed", "unlinked"]
+
+            [profiles.U1]
+            github_username = "zed"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            owner_options(&manifest, &manifest.projects["ctrl"].project_owners),
+            vec!["zed", "unlinked"]
+        );
+
+        // Current owners survive Slack's option limit, however they sort
+        for i in 0..MAX_OPTIONS {
+            let mut profile = manifest.profiles["U1"].clone();
+            profile.github_username = format!("a{:03}", i);
+            manifest.profiles.insert(format!("P{}", i), profile);
+        }
+        let owners = owner_options(&manifest, &manifest.projects["ctrl"].project_owners);
+        assert_eq!(owners.len(), MAX_OPTIONS);
+        assert_eq!(owners[..3], ["zed", "unlinked", "a000"]);
+    }
+}
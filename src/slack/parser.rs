@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+
+/// A positional argument of a command.
+pub struct Arg {
+    pub name: &'static str,
+    pub required: bool,
+}
+
+impl Arg {
+    pub const fn required(name: &'static str) -> Arg {
+        Arg {
+            name,
+            required: true,
+        }
+    }
+
+    pub const fn optional(name: &'static str) -> Arg {
+        Arg {
+            name,
+            required: false,
+        }
+    }
+}
+
+/// A `--name <value>` option of a command.
+pub struct Flag {
+    pub name: &'static str,
+    pub value: &'static str,
+    pub about: &'static str,
+}
+
+/// Describes a command, used both to parse it and to generate its usage and help text.
+pub struct Spec {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub args: &'static [Arg],
+    pub flags: &'static [Flag],
+    pub subcommands: &'static [Spec],
+}
+
+impl Spec {
+    pub const fn new(name: &'static str, about: &'static str) -> Spec {
+        Spec {
+            name,
+            about,
+            args: &[],
+            flags: &[],
+            subcommands: &[],
+        }
+    }
+
+    pub const fn args(mut self, args: &'static [Arg]) -> Spec {
+        self.args = args;
+        self
+    }
+
+    pub const fn flags(mut self, flags: &'static [Flag]) -> Spec {
+        self.flags = flags;
+        self
+    }
+
+    pub const fn subcommands(mut self, subcommands: &'static [Spec]) -> Spec {
+        self.subcommands = subcommands;
+        self
+    }
+
+    fn find(specs: &'static [Spec], name: &str) -> Option<&'static Spec> {
+        specs.iter().find(|spec| spec.name == name)
+    }
+
+    /// The usage line of each leaf command under this one, e.g.
+    /// `/ctrl me github <github_username>`, along with its spec.
+    pub fn usage(&'static self, prefix: &str) -> Vec<(String, &'static Spec)> {
+        let prefix = format!("{} {}", prefix, self.name);
+
+        if !self.subcommands.is_empty() {
+            return self
+                .subcommands
+                .iter()
+                .flat_map(|spec| spec.usage(&prefix))
+                .collect();
+        }
+
+        let args = self.args.iter().map(|arg| match arg.required {
+            true => format!(" <{}>", arg.name),
+            false => format!(" [{}]", arg.name),
+        });
+        let flags = self
+            .flags
+            .iter()
+            .map(|flag| format!(" [--{} <{}>]", flag.name, flag.value));
+
+        vec![(
+            args.chain(flags).fold(prefix, |usage, part| usage + &part),
+            self,
+        )]
+    }
+
+    /// A help entry for each leaf command under this one, including its options.
+    pub fn help(&'static self, prefix: &str) -> Vec<String> {
+        self.usage(prefix)
+            .into_iter()
+            .map(|(usage, spec)| {
+                spec.flags
+                    .iter()
+                    .fold(format!("- `{}`: {}", usage, spec.about), |help, flag| {
+                        format!(
+                            "{}\n    - `--{} <{}>`: {}",
+                            help, flag.name, flag.value, flag.about
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// Nothing was given after the command name.
+    Empty,
+    /// The command line could not be split into words, e.g. an unclosed quote.
+    Syntax(String),
+    /// The first word is not a known command.
+    UnknownCommand(String),
+    /// A known command was used incorrectly.
+    Usage { message: String, usage: String },
+}
+
+/// The command path and values parsed from a command line.
+#[derive(Debug)]
+pub struct Matches {
+    /// The command and subcommand names, e.g. `["me", "github"]`.
+    pub path: Vec<&'static str>,
+    values: HashMap<&'static str, String>,
+}
+
+impl Matches {
+    /// The value of an argument or flag, if it was given.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values.get(name).cloned()
+    }
+
+    /// The value of a required argument, which the parser has already checked for.
+    pub fn value(&self, name: &str) -> String {
+        self.get(name).unwrap_or_default()
+    }
+}
+
+/// Parses `text` against `specs`. `prefix` is used in usage messages, e.g. `/ctrl`.
+pub fn parse(specs: &'static [Spec], prefix: &str, text: &str) -> Result<Matches, ParseError> {
+    let mut tokens = tokenize(text).map_err(ParseError::Syntax)?.into_iter();
+
+    let name = tokens.next().ok_or(ParseError::Empty)?;
+    let mut spec = Spec::find(specs, &name).ok_or(ParseError::UnknownCommand(name))?;
+    let mut path = vec![spec.name];
+    let mut usage_prefix = prefix.to_string();
+
+    let usage_error =
+        |spec: &'static Spec, usage_prefix: &str, message: String| ParseError::Usage {
+            message,
+            usage: spec
+                .usage(usage_prefix)
+                .into_iter()
+                .map(|(usage, _)| format!("`{}`", usage))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+
+    while !spec.subcommands.is_empty() {
+        let subcommand = match tokens.next() {
+            Some(subcommand) => subcommand,
+            None => {
+                return Err(usage_error(
+                    spec,
+                    &usage_prefix,
+                    "Missing subcommand.".to_string(),
+                ))
+            }
+        };
+
+        match Spec::find(spec.subcommands, &subcommand) {
+            Some(found) => {
+                usage_prefix = format!("{} {}", usage_prefix, spec.name);
+                spec = found;
+                path.push(spec.name);
+            }
+            None => {
+                return Err(usage_error(
+                    spec,
+                    &usage_prefix,
+                    format!("Unknown subcommand `{}`.", subcommand),
+                ))
+            }
+        }
+    }
+
+    let mut values = HashMap::new();
+    let mut args = spec.args.iter();
+
+    while let Some(token) = tokens.next() {
+        if let Some(flag) = token.strip_prefix("--") {
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (flag.to_string(), None),
+            };
+
+            let spec_flag = match spec.flags.iter().find(|f| f.name == flag) {
+                Some(spec_flag) => spec_flag,
+                None => {
+                    return Err(usage_error(
+                        spec,
+                        &usage_prefix,
+                        format!("Unknown option `--{}`.", flag),
+                    ))
+                }
+            };
+
+            match value.or_else(|| tokens.next()) {
+                Some(value) => values.insert(spec_flag.name, value),
+                None => {
+                    return Err(usage_error(
+                        spec,
+                        &usage_prefix,
+                        format!("Missing value for `--{}`.", flag),
+                    ))
+                }
+            };
+            continue;
+        }
+
+        match args.next() {
+            Some(arg) => values.insert(arg.name, token),
+            None => {
+                return Err(usage_error(
+                    spec,
+                    &usage_prefix,
+                    format!(
+                        "Unexpected argument `{}`. Use quotes for values with spaces.",
+                        token
+                    ),
+                ))
+            }
+        };
+    }
+
+    if let Some(arg) = args.find(|arg| arg.required) {
+        return Err(usage_error(
+            spec,
+            &usage_prefix,
+            format!("Missing `<{}>`.", arg.name),
+        ));
+    }
+
+    Ok(Matches { path, values })
+}
+
+/// Splits a command line on whitespace. A quote anywhere in a word, including the
+/// curly quotes Slack substitutes, runs until the matching closing quote.
+pub fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut token = String::new();
+
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            let closing = match c {
+                '"' => '"',
+                '\'' => '\'',
+                '“' => '”',
+                '‘' => '’',
+                _ => {
+                    token.push(c);
+                    continue;
+                }
+            };
+
+            loop {
+                match chars.next() {
+                    Some(c) if c == closing => break,
+                    Some(c) => token.push(c),
+                    None => return Err(format!("Missing closing `{}`.", closing)),
+                }
+            }
+        }
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SPECS: &[Spec] = &[
+        Spec::new("github", "Set the repository.").args(&[Arg::required("repo_name")]),
+        Spec::new("merge", "Change merging.").flags(&[Flag {
+            name: "approvals",
+            value: "count",
+            about: "How many approvals.",
+        }]),
+        Spec::new("me", "Manage your accounts.").subcommands(&[Spec::new(
+            "github",
+            "Set your GitHub username.",
+        )
+        .args(&[Arg::required("github_username")])]),
+    ];
+
+    fn usage_message(result: Result<Matches, ParseError>) -> String {
+        match result {
+            Err(ParseError::Usage { message, .. }) => message,
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tokenizes_quoted_words() {
+        assert_eq!(
+            tokenize(r#"set "release/*" 'two words' “curly quotes”"#).unwrap(),
+            vec!["set", "release/*", "two words", "curly quotes"]
+        );
+    }
+
+    #[test]
+    fn tokenizes_quotes_inside_words() {
+        assert_eq!(
+            tokenize(r#"--flag="a b" release/"x y"/*"#).unwrap(),
+            vec!["--flag=a b", "release/x y/*"]
+        );
+    }
+
+    #[test]
+    fn rejects_unclosed_quotes() {
+        assert_eq!(
+            tokenize(r#"set "release/*"#),
+            Err("Missing closing `\"`.".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_arguments_and_subcommands() {
+        let matches = parse(SPECS, "/ctrl", "me github octocat").unwrap();
+
+        assert_eq!(matches.path, vec!["me", "github"]);
+        assert_eq!(matches.get("github_username"), Some("octocat".to_string()));
+    }
+
+    #[test]
+    fn parses_flags_with_and_without_equals() {
+        for text in [
+            "merge --approvals 2",
+            "merge --approvals=2",
+            r#"merge --approvals="2""#,
+        ] {
+            let matches = parse(SPECS, "/ctrl", text).unwrap();
+
+            assert_eq!(matches.get("approvals"), Some("2".to_string()));
+        }
+    }
+
+    #[test]
+    fn reports_unknown_commands() {
+        assert!(matches!(parse(SPECS, "/ctrl", ""), Err(ParseError::Empty)));
+        assert_eq!(
+            parse(SPECS, "/ctrl", "deploy").unwrap_err(),
+            ParseError::UnknownCommand("deploy".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_misuse_with_usage() {
+        assert_eq!(
+            usage_message(parse(SPECS, "/ctrl", "github")),
+            "Missing `<repo_name>`."
+        );
+        assert_eq!(
+            usage_message(parse(SPECS, "/ctrl", "github a b")),
+            "Unexpected argument `b`. Use quotes for values with spaces."
+        );
+        assert_eq!(
+            usage_message(parse(SPECS, "/ctrl", "merge --count 2")),
+            "Unknown option `--count`."
+        );
+        assert_eq!(
+            usage_message(parse(SPECS, "/ctrl", "merge --approvals")),
+            "Missing value for `--approvals`."
+        );
+        assert_eq!(
+            usage_message(parse(SPECS, "/ctrl", "me")),
+            "Missing subcommand."
+        );
+
+        match parse(SPECS, "/ctrl", "me jira") {
+            Err(ParseError::Usage { message, usage }) => {
+                assert_eq!(message, "Unknown subcommand `jira`.");
+                assert_eq!(usage, "`/ctrl me github <github_username>`");
+            }
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn generates_help_with_options() {
+        assert_eq!(
+            SPECS[1].help("/ctrl"),
+            vec!["- `/ctrl merge [--approvals <count>]`: Change merging.\n    - `--approvals <count>`: How many approvals."]
+        );
+    }
+}
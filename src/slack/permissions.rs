@@ -0,0 +1,257 @@
+use super::command::{Command, CommandContext};
+use crate::config::{get_project_by_name, get_user_by_slack_id, Manifest};
+
+/// What a user may do with `/ctrl`, from their linked GitHub username. Each role
+/// can do everything the ones before it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Anyone, who can run read-only commands and manage their own profile.
+    Member,
+    /// A project owner, who can edit their own project.
+    Owner,
+    /// A global manager, who can also create and delete projects and roll back changes.
+    Manager,
+}
+
+impl Role {
+    /// The role of a Slack user, where `project` is the project the command is about.
+    pub fn of(manifest: &Manifest, slack_id: &str, project: Option<&str>) -> Role {
+        let github_username = match get_user_by_slack_id(manifest, slack_id) {
+            Some(profile) => &profile.github_username,
+            None => return Role::Member,
+        };
+
+        if manifest.managers.contains(github_username) {
+            return Role::Manager;
+        }
+
+        match project.and_then(|project| get_project_by_name(manifest, project)) {
+            Some(project) if project.project_owners.contains(github_username) => Role::Owner,
+            _ => Role::Member,
+        }
+    }
+
+    /// Who has this role, for replies to denied commands and actions.
+    pub fn describe(self, project: Option<&str>) -> String {
+        match (self, project) {
+            (Role::Member, _) => "everyone".to_string(),
+            (Role::Owner, Some(project)) => {
+                format!("the owners of project `{}` and managers", project)
+            }
+            (Role::Owner, None) => "project owners and managers".to_string(),
+            (Role::Manager, _) => "managers".to_string(),
+        }
+    }
+}
+
+impl Command {
+    /// The role needed to run this command. Commands that show settings without
+    /// changing them are open to everyone.
+    pub fn required_role(&self) -> Role {
+        match self {
+            // Linking someone else's account would hand them the roles it has
+            Command::MeGitHub { user: Some(_), .. } => Role::Manager,
+            Command::Help { .. }
+            | Command::List
+            | Command::Project
+            | Command::BranchesList
+            | Command::DigestNow
+            | Command::History { .. }
+            | Command::MeGitHub { .. }
+            | Command::MeJira { .. } => Role::Member,
+            Command::Merge {
+                method: None,
+                approvals: None,
+                owner_approval: None,
+                checks: None,
+            }
+            | Command::Reviewers {
+                strategy: None,
+                count: None,
+            } => Role::Member,
+            Command::Edit
+            | Command::Add { .. }
+            | Command::Remove { .. }
+            | Command::GitHub { .. }
+            | Command::Jira { .. }
+            | Command::BranchesSet { .. }
+            | Command::BranchesRemove { .. }
+            | Command::BranchesReset
+            | Command::Merge { .. }
+            | Command::Reviewers { .. } => Role::Owner,
+            Command::Create { .. } | Command::Delete { .. } | Command::Rollback { .. } => {
+                Role::Manager
+            }
+        }
+    }
+
+    /// The command's name as typed, e.g. `branches set`, for replies.
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Help { .. } => "help",
+            Command::List => "list",
+            Command::Project => "project",
+            Command::Create { .. } => "create",
+            Command::Edit => "edit",
+            Command::Delete { .. } => "delete",
+            Command::Add { .. } => "add",
+            Command::Remove { .. } => "remove",
+            Command::GitHub { .. } => "github",
+            Command::Jira { .. } => "jira",
+            Command::BranchesList => "branches list",
+            Command::BranchesSet { .. } => "branches set",
+            Command::BranchesRemove { .. } => "branches remove",
+            Command::BranchesReset => "branches reset",
+            Command::Merge { .. } => "merge",
+            Command::Reviewers { .. } => "reviewers",
+            Command::DigestNow => "digest now",
+            Command::History { .. } => "history",
+            Command::Rollback { .. } => "rollback",
+            Command::MeGitHub { .. } => "me github",
+            Command::MeJira { .. } => "me jira",
+        }
+    }
+}
+
+/// Checks that the user running a command has the role it needs, returning the
+/// reply to send when they don't.
+pub fn check(ctx: &CommandContext, command: &Command) -> Result<(), String> {
+    let required = command.required_role();
+    let project = ctx.project.as_deref();
+
+    // Project commands outside a project channel are answered by the handler
+    if ctx.role >= required || (required == Role::Owner && project.is_none()) {
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "Only {} can run `/ctrl {}`.",
+        required.describe(project),
+        command.name()
+    );
+
+    let manifest = crate::config::read_manifest();
+    if get_user_by_slack_id(&manifest, &ctx.user_id).is_none() {
+        message.push_str(
+            "\nIf that includes you, link your GitHub account first with `/ctrl me github <github_username>`.",
+        );
+    }
+
+    Err(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::schema;
+
+    fn manifest() -> Manifest {
+        schema::parse(
+            r#"
+            version = 1
+            managers = ["boss"]
+            configured_project = "ctrl"
+
+            [projects.ctrl]
+            slack_channel = "C1"
+            project_owners = ["owner"]
+
+            [profiles.U1]
+            github_username = "boss"
+
+            [profiles.U2]
+            github_username = "owner"
+
+            [profiles.U3]
+            github_username = "someone"
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn context(role: Role, project: Option<&str>) -> CommandContext {
+        CommandContext {
+            user_id: "U1".to_string(),
+            user_name: "user".to_string(),
+            channel_id: "C1".to_string(),
+            text: String::new(),
+            trigger_id: None,
+            project: project.map(String::from),
+            role,
+        }
+    }
+
+    #[test]
+    fn roles_come_from_the_linked_github_username() {
+        let manifest = manifest();
+
+        assert_eq!(Role::of(&manifest, "U1", None), Role::Manager);
+        assert_eq!(Role::of(&manifest, "U2", Some("ctrl")), Role::Owner);
+        assert_eq!(Role::of(&manifest, "U2", Some("other")), Role::Member);
+        assert_eq!(Role::of(&manifest, "U2", None), Role::Member);
+        assert_eq!(Role::of(&manifest, "U3", Some("ctrl")), Role::Member);
+        assert_eq!(Role::of(&manifest, "U4", Some("ctrl")), Role::Member);
+    }
+
+    #[test]
+    fn showing_settings_is_open_to_everyone() {
+        let merge = Command::Merge {
+            method: None,
+            approvals: None,
+            owner_approval: None,
+            checks: None,
+        };
+        assert_eq!(merge.required_role(), Role::Member);
+
+        let merge = Command::Merge {
+            method: None,
+            approvals: Some("2".to_string()),
+            owner_approval: None,
+            checks: None,
+        };
+        assert_eq!(merge.required_role(), Role::Owner);
+    }
+
+    #[test]
+    fn only_managers_link_usernames_for_others() {
+        let own = Command::MeGitHub {
+            github_username: "octocat".to_string(),
+            user: None,
+        };
+        let other = Command::MeGitHub {
+            github_username: "octocat".to_string(),
+            user: Some("<@U3>".to_string()),
+        };
+
+        assert_eq!(own.required_role(), Role::Member);
+        assert_eq!(other.required_role(), Role::Manager);
+        assert_eq!(
+            Command::Rollback {
+                change_id: "1".to_string()
+            }
+            .required_role(),
+            Role::Manager
+        );
+    }
+
+    #[test]
+    fn allows_users_with_the_required_role() {
+        let github = Command::GitHub {
+            repo_name: "octo/ctrl".to_string(),
+        };
+
+        assert!(check(&context(Role::Owner, Some("ctrl")), &github).is_ok());
+        assert!(check(&context(Role::Manager, Some("ctrl")), &github).is_ok());
+        // Answered by the handler, which asks for a project channel
+        assert!(check(&context(Role::Member, None), &github).is_ok());
+    }
+
+    #[test]
+    fn describes_who_has_a_role() {
+        assert_eq!(
+            Role::Owner.describe(Some("ctrl")),
+            "the owners of project `ctrl` and managers"
+        );
+        assert_eq!(Role::Manager.describe(Some("ctrl")), "managers");
+    }
+}
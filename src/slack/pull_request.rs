@@ -0,0 +1,622 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use chrono::{Duration, Utc};
+use rocket::serde::json::{json, Value};
+use slack_rust::{
+    block::{
+        block_actions::ActionBlock,
+        block_context::ContextBlock,
+        block_elements::{BlockElement, ButtonElement, MixedElement},
+        block_object::{ConfirmationBlockObject, TextBlockObject, TextBlockType},
+        block_section::SectionBlock,
+        blocks::Block,
+    },
+    chat::{
+        post_message::{post_message, PostMessageRequest},
+        update::{update, UpdateRequest},
+    },
+    http_client::default_client,
+    payloads::interactive::InteractivePayload,
+};
+
+use super::{handler::respond_ephemeral, permissions::Role};
+use crate::{
+    config::{
+        get_project_name_by_github_repo, get_user_by_slack_id, read_manifest,
+        state::{pull_request_key, read_state, update_state, PullRequestThread},
+    },
+    error::{self, Error},
+    github::merge::try_merge,
+};
+
+/// The `block_id` of the buttons on pull request messages, to recognise their actions.
+pub const ACTIONS_BLOCK: &str = "pull_request";
+
+/// How long "Snooze 1 day" holds back review reminders.
+const SNOOZE_HOURS: i64 = 24;
+
+/// The threads of closed pull requests are forgotten after this many days.
+const THREAD_DAYS: i64 = 30;
+
+/// Held by `owner/repo#number` while a pull request's thread is looked up, posted to
+/// and saved, so events that arrive together don't each start a thread.
+static THREAD_LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    OnceLock::new();
+
+/// A button on pull request messages, other than the link to GitHub.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Approve,
+    Merge,
+    RequestChanges,
+    Snooze,
+}
+
+impl Action {
+    const ALL: [Action; 4] = [
+        Action::Approve,
+        Action::Merge,
+        Action::RequestChanges,
+        Action::Snooze,
+    ];
+
+    fn id(self) -> &'static str {
+        match self {
+            Action::Approve => "approve",
+            Action::Merge => "merge",
+            Action::RequestChanges => "request_changes",
+            Action::Snooze => "snooze",
+        }
+    }
+
+    fn parse(action_id: &str) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.id() == action_id)
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Action::Approve => "Approve",
+            Action::Merge => "Merge",
+            Action::RequestChanges => "Request changes",
+            Action::Snooze => "Snooze 1 day",
+        }
+    }
+
+    fn style(self) -> Option<&'static str> {
+        match self {
+            Action::Approve => Some("primary"),
+            Action::RequestChanges => Some("danger"),
+            _ => None,
+        }
+    }
+
+    /// The role needed to press the button. Snoozing only quiets ctrl's reminders,
+    /// so anyone with a linked account can do it.
+    fn required_role(self) -> Role {
+        match self {
+            Action::Snooze => Role::Member,
+            _ => Role::Owner,
+        }
+    }
+
+    /// What the button does, e.g. `merge PRs`, for replies to denied presses.
+    fn describe(self) -> &'static str {
+        match self {
+            Action::Approve => "approve PRs",
+            Action::Merge => "merge PRs",
+            Action::RequestChanges => "request changes on PRs",
+            Action::Snooze => "snooze PRs",
+        }
+    }
+}
+
+/// What happened to a pull request, deciding how its Slack thread changes.
+pub enum Event<'a> {
+    /// Opened or reopened, with the reviewers ctrl requested.
+    Opened {
+        reviewers: &'a [String],
+    },
+    /// A review was requested by hand. Only announced in an existing thread, and
+    /// not for reviewers ctrl requested itself.
+    ReviewRequested {
+        reviewer: &'a str,
+    },
+    Updated,
+    Merged,
+    /// Closed without merging. Only announced in an existing thread.
+    Closed,
+}
+
+/// Posts a message about a pull request to `channel_id`. The first message starts
+/// the pull request's thread and has buttons to act on it; later ones reply in the
+/// thread and edit the first message to show `status`, or keep the current status
+/// when it's `None`.
+pub async fn notify(
+    channel_id: &str,
+    repo: &str,
+    number: u64,
+    event: Event<'_>,
+    text: String,
+    status: Option<String>,
+) -> error::Result<()> {
+    let key = pull_request_key(repo, number);
+    let thread_lock = thread_lock(&key);
+    let _thread = thread_lock.lock().await;
+
+    let existing = read_state()
+        .pull_request_threads
+        .remove(&key)
+        .filter(|thread| thread.channel_id == channel_id);
+
+    let skip = match (&event, &existing) {
+        (Event::ReviewRequested { reviewer }, Some(thread)) => {
+            thread.reviewers.iter().any(|f| f == reviewer)
+        }
+        (Event::ReviewRequested { .. } | Event::Closed, None) => true,
+        (Event::Merged | Event::Closed, Some(thread)) => thread.closed_at.is_some(),
+        _ => false,
+    };
+    if skip {
+        return Ok(());
+    }
+
+    let closed_at = match event {
+        Event::Merged | Event::Closed => Some(Utc::now()),
+        _ => None,
+    };
+
+    let mut thread = match existing {
+        Some(mut thread) => {
+            post(channel_id, text, None, Some(thread.ts.clone())).await?;
+
+            if let Some(status) = status {
+                thread.status = status;
+            }
+            if closed_at.is_some() || matches!(event, Event::Opened { .. }) {
+                thread.closed_at = closed_at;
+            }
+            update_first_message(repo, number, &thread).await?;
+            thread
+        }
+        None => {
+            let status = status.unwrap_or_default();
+            let blocks = blocks(repo, number, &text, &status, closed_at.is_none());
+            let ts = post(channel_id, text.clone(), Some(blocks), None).await?;
+
+            PullRequestThread {
+                channel_id: channel_id.to_string(),
+                ts,
+                text,
+                status,
+                reviewers: vec![],
+                closed_at,
+            }
+        }
+    };
+
+    match event {
+        Event::Opened { reviewers } => thread.reviewers.extend(reviewers.iter().cloned()),
+        Event::ReviewRequested { reviewer } => thread.reviewers.push(reviewer.to_string()),
+        _ => (),
+    }
+    thread.reviewers.sort();
+    thread.reviewers.dedup();
+
+    save_thread(&key, thread);
+    Ok(())
+}
+
+/// Shows `status` on the first message of a pull request's thread without posting,
+/// if it has one.
+pub async fn set_status(repo: &str, number: u64, status: String) -> error::Result<()> {
+    let key = pull_request_key(repo, number);
+    let thread_lock = thread_lock(&key);
+    let _thread = thread_lock.lock().await;
+
+    let mut thread = match read_state().pull_request_threads.remove(&key) {
+        Some(thread) => thread,
+        None => return Ok(()),
+    };
+
+    thread.status = status;
+    update_first_message(repo, number, &thread).await?;
+
+    save_thread(&key, thread);
+    Ok(())
+}
+
+/// The lock for a pull request's thread, forgetting the locks no one holds.
+fn thread_lock(key: &str) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = THREAD_LOCKS.get_or_init(Default::default).lock().unwrap();
+    locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+    locks.entry(key.to_string()).or_default().clone()
+}
+
+/// Stores a thread, forgetting the threads of pull requests closed long ago.
+fn save_thread(key: &str, thread: PullRequestThread) {
+    let forget_before = Utc::now() - Duration::days(THREAD_DAYS);
+
+    update_state(|state| {
+        state.pull_request_threads.insert(key.to_string(), thread);
+        state.pull_request_threads.retain(
+            |_, thread| !matches!(thread.closed_at, Some(closed_at) if closed_at < forget_before),
+        );
+    });
+}
+
+/// Posts a message, or a reply when `thread_ts` is set, returning its `ts`.
+async fn post(
+    channel_id: &str,
+    text: String,
+    blocks: Option<Vec<Block>>,
+    thread_ts: Option<String>,
+) -> error::Result<String> {
+    let slack_bot_token = env::var("SLACK_BOT_TOKEN")
+        .map_err(|_| Error::Slack("SLACK_BOT_TOKEN is not set".to_string()))?;
+
+    let mut request = PostMessageRequest::builder(channel_id.to_string()).text(text);
+    if let Some(blocks) = blocks {
+        request = request.blocks(blocks);
+    }
+    if let Some(thread_ts) = thread_ts {
+        request = request.thread_ts(thread_ts);
+    }
+
+    let response = post_message(&default_client(), &request.build(), &slack_bot_token).await?;
+
+    match (response.ok, response.ts) {
+        (true, Some(ts)) => Ok(ts),
+        _ => Err(Error::Slack(format!(
+            "chat.postMessage failed: {}",
+            response.error.unwrap_or_default()
+        ))),
+    }
+}
+
+/// Rebuilds the first message of a thread with its current status. Once the pull
+/// request is closed only the link to GitHub is kept.
+async fn update_first_message(
+    repo: &str,
+    number: u64,
+    thread: &PullRequestThread,
+) -> error::Result<()> {
+    let slack_bot_token = env::var("SLACK_BOT_TOKEN")
+        .map_err(|_| Error::Slack("SLACK_BOT_TOKEN is not set".to_string()))?;
+
+    let request = UpdateRequest {
+        channel: thread.channel_id.clone(),
+        ts: thread.ts.clone(),
+        blocks: Some(blocks(
+            repo,
+            number,
+            &thread.text,
+            &thread.status,
+            thread.closed_at.is_none(),
+        )),
+        text: Some(thread.text.clone()),
+        ..Default::default()
+    };
+    let response = update(&default_client(), &request, &slack_bot_token).await?;
+
+    match response.ok {
+        true => Ok(()),
+        false => Err(Error::Slack(format!(
+            "chat.update failed: {}",
+            response.error.unwrap_or_default()
+        ))),
+    }
+}
+
+fn blocks(repo: &str, number: u64, text: &str, status: &str, open: bool) -> Vec<Block> {
+    let key = pull_request_key(repo, number);
+
+    let mut buttons = match open {
+        true => Action::ALL
+            .into_iter()
+            .map(|action| {
+                let mut button =
+                    ButtonElement::builder(plain_text(action.label()), action.id().to_string())
+                        .value(key.clone());
+                if let Some(style) = action.style() {
+                    button = button.style(style.to_string());
+                }
+                if action == Action::Merge {
+                    button = button.confirm(ConfirmationBlockObject {
+                        title: plain_text("Merge this PR?"),
+                        text: plain_text(
+                            "It is merged once it has the approvals on GitHub and passing checks the project needs.",
+                        ),
+                        confirm: plain_text("Merge"),
+                        deny: plain_text("Cancel"),
+                        style: None,
+                    });
+                }
+
+                BlockElement::ButtonElement(button.build())
+            })
+            .collect::<Vec<BlockElement>>(),
+        false => vec![],
+    };
+    buttons.push(BlockElement::ButtonElement(
+        ButtonElement::builder(plain_text("Open in GitHub"), "open".to_string())
+            .url(format!("https://github.com/{}/pull/{}", repo, number))
+            .build(),
+    ));
+
+    let mut blocks = vec![Block::SectionBlock(SectionBlock {
+        text: Some(TextBlockObject::builder(TextBlockType::Mrkdwn, text.to_string()).build()),
+        ..Default::default()
+    })];
+    if !status.is_empty() {
+        blocks.push(Block::ContextBlock(ContextBlock {
+            elements: vec![MixedElement::MarkdownBlockObject {
+                text: format!("*Status:* {}", status),
+                emoji: None,
+                verbatim: None,
+            }],
+            block_id: None,
+        }));
+    }
+    blocks.push(Block::ActionBlock(
+        ActionBlock::builder(buttons)
+            .block_id(ACTIONS_BLOCK.to_string())
+            .build(),
+    ));
+
+    blocks
+}
+
+fn plain_text(text: &str) -> TextBlockObject {
+    TextBlockObject::builder(TextBlockType::PlainText, text.to_string()).build()
+}
+
+/// Handles a press of one of the buttons on a pull request message, acting as the
+/// GitHub user linked to whoever pressed it.
+pub async fn handle_action(payload: InteractivePayload) -> error::Result<()> {
+    let pressed = payload
+        .actions
+        .as_ref()
+        .and_then(|actions| actions.first())
+        .ok_or(Error::Payload("the interaction has no action".to_string()))?;
+
+    // "Open in GitHub" only opens the link
+    let action = match pressed.action_id.as_deref().and_then(Action::parse) {
+        Some(action) => action,
+        None => return Ok(()),
+    };
+
+    let value = pressed.value.clone().unwrap_or_default();
+    let (repo, number) = value
+        .rsplit_once('#')
+        .and_then(|(repo, number)| Some((repo.to_string(), number.parse::<u64>().ok()?)))
+        .ok_or_else(|| Error::Payload(format!("unreadable pull request `{}`", value)))?;
+
+    let user_id = payload
+        .user
+        .as_ref()
+        .and_then(|user| user.id.clone())
+        .ok_or(Error::Payload("the interaction has no user ID".to_string()))?;
+    let container = payload.container.as_ref().ok_or(Error::Payload(
+        "the interaction has no container".to_string(),
+    ))?;
+    let (channel_id, message_ts) = match (&container.channel_id, &container.message_ts) {
+        (Some(channel_id), Some(message_ts)) => (channel_id.clone(), message_ts.clone()),
+        _ => {
+            return Err(Error::Payload(
+                "the interaction is not on a message".to_string(),
+            ))
+        }
+    };
+
+    let manifest = read_manifest();
+    let project_name = match get_project_name_by_github_repo(&manifest, &repo) {
+        Some(project_name) => project_name,
+        None => {
+            respond_ephemeral(
+                &channel_id,
+                &user_id,
+                format!(
+                    "No project uses <https://github.com/{0}|{0}> any more.",
+                    repo
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let project = &manifest.projects[project_name];
+
+    let github_username = match get_user_by_slack_id(&manifest, &user_id) {
+        Some(profile) => profile.github_username.clone(),
+        None => {
+            respond_ephemeral(
+                &channel_id,
+                &user_id,
+                "Link your GitHub account first with `/ctrl me github <github_username>`."
+                    .to_string(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let required = action.required_role();
+    if Role::of(&manifest, &user_id, Some(project_name)) < required {
+        respond_ephemeral(
+            &channel_id,
+            &user_id,
+            format!(
+                "Only {} can {} from Slack.",
+                required.describe(Some(project_name)),
+                action.describe()
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    println!(
+        "{} pressed {:?} on {}#{}",
+        github_username, action, repo, number
+    );
+
+    let done = match action {
+        Action::Snooze => {
+            snooze(&repo, number);
+            Ok(false)
+        }
+        _ => act_on_github(action, &repo, number, &github_username).await,
+    };
+
+    let merge = match done {
+        Ok(merge) => merge,
+        Err(reason) => {
+            respond_ephemeral(&channel_id, &user_id, reason).await?;
+            return Ok(());
+        }
+    };
+
+    // Messages from before threads were kept become the pull request's thread
+    let key = pull_request_key(&repo, number);
+    if !read_state().pull_request_threads.contains_key(&key) {
+        let message = payload.message.unwrap_or_default();
+        save_thread(
+            &key,
+            PullRequestThread {
+                channel_id: channel_id.clone(),
+                ts: message_ts,
+                text: message.text.unwrap_or_default(),
+                status: String::new(),
+                reviewers: vec![],
+                closed_at: None,
+            },
+        );
+    }
+
+    let (event, text, status) = match action {
+        Action::Approve => (
+            Event::Updated,
+            format!(
+                "✅ <@{}> approved this PR from Slack. This is advisory, approve it on GitHub for it to count towards merging.",
+                user_id
+            ),
+            Some(format!("✅ Approved from Slack by <@{}>", user_id)),
+        ),
+        Action::RequestChanges => (
+            Event::Updated,
+            format!("✏️ <@{}> requested changes.", user_id),
+            Some(format!("✏️ Changes requested by <@{}>", user_id)),
+        ),
+        Action::Merge => (
+            Event::Updated,
+            format!("🔀 <@{}> asked to merge this PR.", user_id),
+            None,
+        ),
+        Action::Snooze => (
+            Event::Updated,
+            format!(
+                "💤 <@{}> snoozed the reminders for this PR for a day.",
+                user_id
+            ),
+            None,
+        ),
+    };
+
+    notify(&channel_id, &repo, number, event, text, status).await?;
+
+    // Merged once the merge settings are met, showing what it still waits on in the thread
+    if merge {
+        try_merge(project, number, &github_username, false).await?;
+    }
+
+    Ok(())
+}
+
+/// Acts on a pull request from Slack, returning whether to merge it afterwards or
+/// why it couldn't be done. Reviews are posted to GitHub as comments from ctrl's
+/// account, so they are advisory: only reviews given on GitHub count towards merging.
+async fn act_on_github(
+    action: Action,
+    repo: &str,
+    number: u64,
+    github_username: &str,
+) -> Result<bool, String> {
+    let details = repo.split('/').collect::<Vec<&str>>();
+    let pull_request = octocrab::instance()
+        .pulls(details[0], details[1])
+        .get(number)
+        .await
+        .map_err(|e| format!("Couldn't get the PR from GitHub: {}", Error::from(e)))?;
+
+    if pull_request.merged_at.is_some() || pull_request.closed_at.is_some() {
+        return Err("This PR has already been closed.".to_string());
+    }
+
+    let author = pull_request.user.map(|user| user.login).unwrap_or_default();
+    if action != Action::Merge && author == github_username {
+        return Err("You can't review your own PR.".to_string());
+    }
+
+    let body = match action {
+        Action::Approve => format!(
+            "✅ @{} approved this from Slack. This doesn't count as an approval on GitHub.",
+            github_username
+        ),
+        Action::RequestChanges => format!(
+            "✏️ @{} requested changes from Slack. @{}, have a look at the discussion in the project's channel.",
+            github_username, author
+        ),
+        // Merged whatever the branch policy, as someone asked for it
+        _ => return Ok(true),
+    };
+
+    if let Err(e) = review(repo, number, body).await {
+        println!("Failed to {} {}#{}: {}", action.id(), repo, number, e);
+        return Err(format!("GitHub refused: {}", e));
+    }
+
+    Ok(false)
+}
+
+/// Comments on a pull request as ctrl's GitHub account. Reviews from Slack are
+/// submitted as comments, as ctrl can't review as the person who pressed the button.
+async fn review(repo: &str, number: u64, body: String) -> error::Result<()> {
+    octocrab::instance()
+        .post::<_, Value>(
+            format!("/repos/{}/pulls/{}/reviews", repo, number),
+            Some(&json!({ "event": "COMMENT", "body": body })),
+        )
+        .await?;
+
+    Ok(())
+}
+
+fn snooze(repo: &str, number: u64) {
+    update_state(|state| {
+        state.snoozed.insert(
+            pull_request_key(repo, number),
+            Utc::now() + Duration::hours(SNOOZE_HOURS),
+        )
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn events_for_a_pull_request_wait_their_turn() {
+        let lock = thread_lock("octo/ctrl#1");
+        let _held = lock.lock().await;
+
+        assert!(thread_lock("octo/ctrl#1").try_lock().is_err());
+        assert!(thread_lock("octo/ctrl#2").try_lock().is_ok());
+    }
+}
@@ -0,0 +1,122 @@
+use rocket::serde::json::serde_json;
+use slack_rust::block::blocks::Block;
+
+use super::handler::{respond_http_blocks, respond_http_text};
+use crate::Response;
+
+/// Somewhere the replies to a command are sent, independent of how the command
+/// arrived.
+#[rocket::async_trait]
+pub trait ReplySink: Send + Sync {
+    async fn text(&self, text: String);
+    async fn blocks(&self, blocks: Vec<Block>);
+}
+
+/// Posts replies into a channel with `chat.postMessage`, used by Socket Mode.
+pub struct ChannelSink {
+    pub channel_id: String,
+}
+
+#[rocket::async_trait]
+impl ReplySink for ChannelSink {
+    async fn text(&self, text: String) {
+        if let Err(e) = respond_http_text(&self.channel_id, text).await {
+            println!("Failed to respond: {}", e);
+        }
+    }
+
+    async fn blocks(&self, blocks: Vec<Block>) {
+        if let Err(e) = respond_http_blocks(&self.channel_id, blocks).await {
+            println!("Failed to respond: {}", e);
+        }
+    }
+}
+
+/// Sends replies to the `response_url` of a slash command received over HTTP.
+pub struct ResponseUrlSink {
+    pub response_url: String,
+}
+
+impl ResponseUrlSink {
+    async fn send(&self, response: Response) {
+        let result = reqwest::Client::new()
+            .post(&self.response_url)
+            .json(&response)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        if let Err(e) = result {
+            println!("Failed to respond through response_url: {}", e);
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl ReplySink for ResponseUrlSink {
+    async fn text(&self, text: String) {
+        self.send(Response::TextResponse {
+            text,
+            response_type: "in_channel".to_string(),
+        })
+        .await
+    }
+
+    async fn blocks(&self, blocks: Vec<Block>) {
+        self.send(Response::BlockResponse {
+            blocks: blocks
+                .iter()
+                .filter_map(|block| serde_json::to_value(block).ok())
+                .collect(),
+            response_type: "in_channel".to_string(),
+        })
+        .await
+    }
+}
+
+/// A reply kept by `RecordingSink`.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Text(String),
+    /// The blocks as the JSON sent to Slack.
+    Blocks(Vec<serde_json::Value>),
+}
+
+/// Keeps replies instead of sending them, so tests can check what a command said.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingSink {
+    replies: std::sync::Mutex<Vec<Reply>>,
+}
+
+#[cfg(test)]
+impl RecordingSink {
+    pub fn replies(&self) -> Vec<Reply> {
+        std::mem::take(&mut *self.replies.lock().unwrap())
+    }
+
+    /// The only reply, which must be text.
+    pub fn text(&self) -> String {
+        match self.replies().as_slice() {
+            [Reply::Text(text)] => text.clone(),
+            replies => panic!("expected a single text reply, got {:?}", replies),
+        }
+    }
+}
+
+#[cfg(test)]
+#[rocket::async_trait]
+impl ReplySink for RecordingSink {
+    async fn text(&self, text: String) {
+        self.replies.lock().unwrap().push(Reply::Text(text));
+    }
+
+    async fn blocks(&self, blocks: Vec<Block>) {
+        let blocks = blocks
+            .iter()
+            .filter_map(|block| serde_json::to_value(block).ok())
+            .collect();
+        self.replies.lock().unwrap().push(Reply::Blocks(blocks));
+    }
+}
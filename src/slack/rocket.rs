@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocket::{
+    data::{self, Data, FromData, Limits},
+    form::Form,
+    http::Status,
+    outcome::Outcome,
+    serde::json::serde_json,
+    Request,
+};
+use slack_rust::payloads::interactive::InteractivePayload;
+
+use crate::Parameters;
+
+/// Requests older (or newer) than this many seconds are treated as replays.
+const MAX_TIMESTAMP_SKEW: i64 = 60 * 5;
+
+/// A slash command payload, only produced once `X-Slack-Signature` matches the
+/// raw body and `X-Slack-Request-Timestamp` is recent.
+pub struct SignedCommand(pub Parameters);
+
+/// The `payload` field of an interaction request, such as a modal submission,
+/// verified like `SignedCommand`.
+pub struct SignedInteraction(pub InteractivePayload);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SignedCommand {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let body = match verified_body(request, data).await {
+            Ok(body) => body,
+            Err(status) => return Outcome::Failure((status, ())),
+        };
+
+        match Form::<Parameters>::parse(&body) {
+            Ok(parameters) => Outcome::Success(SignedCommand(parameters)),
+            Err(e) => {
+                println!("Rejected Slack request: {}", e);
+                Outcome::Failure((Status::BadRequest, ()))
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SignedInteraction {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let body = match verified_body(request, data).await {
+            Ok(body) => body,
+            Err(status) => return Outcome::Failure((status, ())),
+        };
+
+        let payload = Form::<HashMap<String, String>>::parse(&body)
+            .map_err(|e| e.to_string())
+            .and_then(|form| {
+                form.get("payload")
+                    .cloned()
+                    .ok_or("missing payload".to_string())
+            })
+            .and_then(|payload| serde_json::from_str(&payload).map_err(|e| e.to_string()));
+
+        match payload {
+            Ok(payload) => Outcome::Success(SignedInteraction(payload)),
+            Err(e) => {
+                println!("Rejected Slack request: {}", e);
+                Outcome::Failure((Status::BadRequest, ()))
+            }
+        }
+    }
+}
+
+/// Reads the body of a request from Slack, checking its signature and timestamp.
+async fn verified_body(request: &Request<'_>, data: Data<'_>) -> Result<String, Status> {
+    let limit = request.limits().get("form").unwrap_or(Limits::FORM);
+    let body = match data.open(limit).into_string().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        Ok(_) => return Err(Status::PayloadTooLarge),
+        Err(_) => return Err(Status::BadRequest),
+    };
+
+    let secret = match env::var("SLACK_SIGNING_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret,
+        _ => {
+            println!("Rejected Slack request: SLACK_SIGNING_SECRET is not set");
+            return Err(Status::Unauthorized);
+        }
+    };
+
+    let timestamp = request
+        .headers()
+        .get_one("X-Slack-Request-Timestamp")
+        .and_then(|timestamp| timestamp.parse::<i64>().ok());
+
+    let timestamp = match timestamp {
+        Some(timestamp) => timestamp,
+        None => {
+            println!("Rejected Slack request: missing X-Slack-Request-Timestamp");
+            return Err(Status::Unauthorized);
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or_default();
+
+    let signature = request
+        .headers()
+        .get_one("X-Slack-Signature")
+        .and_then(|signature| signature.strip_prefix("v0="));
+
+    match check_signature(&secret, timestamp, &body, signature, now) {
+        Ok(()) => Ok(body),
+        Err(e) => {
+            println!("Rejected Slack request: {}", e);
+            Err(Status::Unauthorized)
+        }
+    }
+}
+
+/// Checks the `v0` signature of a request sent at `timestamp`, which must be within
+/// `MAX_TIMESTAMP_SKEW` of `now`.
+fn check_signature(
+    secret: &str,
+    timestamp: i64,
+    body: &str,
+    signature: Option<&str>,
+    now: i64,
+) -> Result<(), String> {
+    if (now - timestamp).abs() > MAX_TIMESTAMP_SKEW {
+        return Err(format!(
+            "timestamp {} is outside the allowed window",
+            timestamp
+        ));
+    }
+
+    let base = format!("v0:{}:{}", timestamp, body);
+    match signature {
+        Some(signature)
+            if crate::signature::verify(secret.as_bytes(), base.as_bytes(), signature) =>
+        {
+            Ok(())
+        }
+        _ => Err("missing or invalid X-Slack-Signature".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from Slack's guide to verifying requests
+    const SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+    const TIMESTAMP: i64 = 1531420618;
+    const BODY: &str = "token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+    const SIGNATURE: &str = "a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        assert_eq!(
+            check_signature(SECRET, TIMESTAMP, BODY, Some(SIGNATURE), TIMESTAMP + 10),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_a_tampered_body() {
+        let body = BODY.replace("text=", "text=delete");
+
+        assert!(check_signature(SECRET, TIMESTAMP, &body, Some(SIGNATURE), TIMESTAMP).is_err());
+    }
+
+    #[test]
+    fn rejects_a_missing_signature() {
+        assert!(check_signature(SECRET, TIMESTAMP, BODY, None, TIMESTAMP).is_err());
+    }
+
+    #[test]
+    fn rejects_replayed_requests() {
+        let now = TIMESTAMP + MAX_TIMESTAMP_SKEW + 1;
+
+        assert!(check_signature(SECRET, TIMESTAMP, BODY, Some(SIGNATURE), now).is_err());
+        assert!(check_signature(SECRET, now, BODY, Some(SIGNATURE), TIMESTAMP).is_err());
+    }
+}